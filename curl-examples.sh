@@ -10,8 +10,14 @@ curl -X DELETE http://localhost:3000/persons/1
 echo "\n===== person aggregate ====="
 curl http://localhost:3000/persons
 
+echo '\n===== person aggregate page ====='
+curl -D - 'http://localhost:3000/persons?limit=1'
+
 echo '\n===== location aggregate ====='
 curl http://localhost:3000/locations
 
+echo '\n===== location event page ====='
+curl -H 'Accept: application/json' 'http://localhost:3000/location-events?from=1&limit=10'
+
 echo '\n===== location events ====='
 curl -N -H 'X-Revision: 1' http://localhost:3000/location-events
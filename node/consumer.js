@@ -1,28 +1,23 @@
 'use strict'
 
 // Bare minimum implementation of a consumer that builds and maintains two read models.
-// Each read model is initialized with aggregates fetched from the REST API.
-// It is subsequently kept up-to-date with events consumed from the corresponding SSE channel.
+// Each read model is initialized with the aggregates in the first message of the SSE channel.
+// It is subsequently kept up-to-date with the change events that follow on the same channel.
 // The events are merged into the read model with help of library 'json-merge-patch'.
 
-import fetch from "node-fetch";
 import EventSource from 'eventsource'
 import Merger from 'json-merge-patch'
 
 const HOST = 'http://localhost:3000'
-const REVISION_HEADER = 'X-Revision'
 
-async function bootstrap(path) {
-    const response = await fetch(`${HOST}/${path}`)
-    const revision = Number(response.headers.get(REVISION_HEADER)) || 0
-    let records = await response.json()
-    console.log("fetch:", records)
-    return { revision, records }
-}
-
-function subscribe(path, { revision, records }) {
-    const headers = { headers: { [REVISION_HEADER]: revision + 1 }}
-    const eventSource = new EventSource(`${HOST}/${path}`, headers)
+function follow(path) {
+    let records = {}
+    // On reconnect, the server resumes after the last event, or sends the aggregates again
+    const eventSource = new EventSource(`${HOST}/${path}?follow=true`)
+    eventSource.addEventListener('snapshot', event => {
+        records = JSON.parse(event.data)
+        console.log("fetch:", records)
+    })
     eventSource.onmessage = event => {
         const patch = JSON.parse(event.data)
         console.log("patch:", patch)
@@ -31,12 +26,5 @@ function subscribe(path, { revision, records }) {
     }
 }
 
-try {
-    const persons = await bootstrap('persons')
-    const locations = await bootstrap('locations')
-    subscribe('person-events', persons)
-    subscribe('location-events', locations)
-
-} catch (e) {
-    console.warn(e)
-}
+follow('persons')
+follow('locations')
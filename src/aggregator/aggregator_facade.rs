@@ -1,96 +1,340 @@
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use log::{info, warn};
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, Transaction};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::watch;
 use crate::aggregator::aggregator_trait::AggregatorTrait;
 use crate::aggregator::location_aggregator::LocationAggregator;
 use crate::aggregator::person_aggregator::PersonAggregator;
+use crate::aggregator::person_validator::PersonValidator;
+use crate::aggregator::revision_notifier::RevisionNotifier;
+use crate::database::commit_table::CommitTable;
+use crate::database::event_table::{LocationEventTable, PersonEventTable};
+use crate::database::idempotency_table::IdempotencyTable;
+use crate::database::location_table::LocationTable;
 use crate::database::person_table::PersonTable;
 use crate::database::revision_table::RevisionTable;
+use crate::database::snapshot_table::PersonSnapshotTable;
 use crate::domain::event_type::EventType;
+use crate::domain::location_data::LocationData;
 use crate::domain::location_map::LocationMap;
 use crate::domain::person_data::PersonData;
+use crate::domain::person_filter::PersonFilter;
 use crate::domain::person_id::PersonId;
 use crate::domain::person_map::PersonMap;
+use crate::domain::person_operation::PersonOperation;
 use crate::domain::person_patch::PersonPatch;
+use crate::domain::precondition::Precondition;
 use crate::util::deletion_scheduler::DeletionTask;
+use crate::util::merge_patch::apply;
+use crate::error::{DomainError, Result};
+use crate::util::timestamp::{BoxedTimestamp, UnixTimestamp};
 
 ///
 /// This class is the facade to the REST handlers and the scheduler.
 /// It processes and stores person data and delegates to the aggregators.
 /// It also creates the transaction boundary for all database operations.
+/// After every committed write, it notifies the event streams about the new revisions.
 ///
 pub struct AggregatorFacade {
     connection: Connection,
+    retention: EventRetention,
     person_aggr: PersonAggregator,
-    location_aggr: LocationAggregator
+    location_aggr: LocationAggregator,
+    notifier: RevisionNotifier,
+    timestamp: BoxedTimestamp
 }
 
 pub type MutexAggregator = Arc<Mutex<AggregatorFacade>>;
 
+///
+/// Determines how the [DeletionTask](crate::util::deletion_scheduler::DeletionTask) treats outdated events.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EventRetention {
+    /// Delete outdated events. Consumers that did not receive them must reload the aggregates.
+    Delete,
+    /// Fold outdated events into one merge patch per aggregate. Consumers can start from any revision.
+    Compact
+}
+
+impl FromStr for EventRetention {
+    type Err = String;
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "delete" => Ok(EventRetention::Delete),
+            "compact" => Ok(EventRetention::Compact),
+            _ => Err(format!("Event retention must be 'delete' or 'compact', not '{}'", s))
+        }
+    }
+}
+
+/// A commit id together with the event type, revision, and serialized event of all events of that commit.
+pub type CommitEvents = (usize, Vec<(EventType, usize, String)>);
+
+///
+/// Result of reading the aggregates as of an older revision, see [get_persons_at](AggregatorFacade::get_persons_at).
+///
+#[derive(Debug, Eq, PartialEq)]
+pub enum PointInTime<T> {
+    /// The aggregates as of the requested revision
+    Found(T),
+    /// The requested revision was not reached yet, holds the current revision
+    Future(usize),
+    /// The events to revert were deleted or compacted, holds the oldest revision that can be restored
+    Purged(usize)
+}
+
+///
+/// Result of a write with an idempotency key, see [insert_idempotent](AggregatorFacade::insert_idempotent).
+///
+#[derive(Debug, Eq, PartialEq)]
+pub enum Idempotent<T> {
+    /// The write was done, holds its result
+    Done(T),
+    /// An earlier write with the same key was done, holds its result
+    Replayed(T)
+}
+
+///
+/// The results of an applied batch, see [apply_batch](AggregatorFacade::apply_batch).
+/// The revision ranges are ``None`` if the batch emitted no events of their type.
+///
+#[derive(Debug, Eq, PartialEq)]
+pub struct BatchResult {
+    /// Person id, revision and data of each operation, without data for deletions
+    pub results: Vec<(PersonId, usize, Option<PersonData>)>,
+    /// First and last revision of the emitted person events
+    pub person_revisions: Option<(usize, usize)>,
+    /// First and last revision of the emitted location events
+    pub location_revisions: Option<(usize, usize)>
+}
+
+///
+/// A page of persons in the state of a snapshot, see [get_person_page](AggregatorFacade::get_person_page).
+/// Field ``next`` holds the id to continue after, or ``None`` if this is the last page.
+///
+#[derive(Debug, Eq, PartialEq)]
+pub struct PersonPage {
+    pub snapshot: usize,
+    pub revision: usize,
+    pub commit_id: usize,
+    pub persons: PersonMap,
+    pub next: Option<PersonId>
+}
+
 impl AggregatorFacade {
     pub fn new(db_path: &str) -> Result<Self> {
-        let connection = Connection::open(db_path)?;
+        Self::with_retention(db_path, EventRetention::Delete)
+    }
+
+    pub fn with_retention(db_path: &str, retention: EventRetention) -> Result<Self> {
+        let mut connection = Connection::open(db_path)?;
         PersonTable::create_table(&connection)?;
         RevisionTable::create_table(&connection)?;
+        CommitTable::create_table(&connection)?;
+        PersonSnapshotTable::create_table(&connection)?;
+        IdempotencyTable::create_table(&connection)?;
         let mut person_aggr = PersonAggregator::new();
         person_aggr.create_tables(&connection)?;
         let mut location_aggr = LocationAggregator::new();
         location_aggr.create_tables(&connection)?;
-        Ok(Self{ connection, person_aggr, location_aggr })
+        let notifier = RevisionNotifier::new();
+        Self::commit_and_notify(connection.transaction()?, &notifier)?; // Publish stored revisions
+        Ok(Self{ connection, retention, person_aggr, location_aggr, notifier, timestamp: UnixTimestamp::new() })
     }
 
-    pub fn insert(&mut self, person: &PersonData) -> Result<(PersonId, PersonData)> {
+    /// Returns a receiver that is updated with the latest revision of ``event_type`` after each commit.
+    pub fn subscribe(&self, event_type: EventType) -> watch::Receiver<usize> {
+        self.notifier.subscribe(event_type)
+    }
+
+    /// Returns a receiver that is updated with the latest commit id after each commit that wrote events.
+    pub fn subscribe_commits(&self) -> watch::Receiver<usize> {
+        self.notifier.subscribe_commits()
+    }
+
+    /// Creates a person and returns its id, its revision and its data. Invalid data is rejected with [DomainError::Validation].
+    pub fn insert(&mut self, person: &PersonData) -> Result<(PersonId, usize, PersonData)> {
         let tx = self.connection.transaction()?;
-        let person_id = PersonTable::insert(&tx, &person)?;
-        self.person_aggr.insert(&tx, person_id, &person)?;
-        self.location_aggr.insert(&tx, person_id, &person)?;
-        tx.commit()?;
+        let errors = PersonValidator::validate_data(&tx, None, person)?;
+        if !errors.is_empty() {
+            return Self::reject(tx, None, DomainError::Validation(errors))
+        }
+        let (person_id, revision) = Self::insert_person(&mut self.person_aggr, &mut self.location_aggr, &tx, None, person)?;
+        Self::commit_and_notify(tx, &self.notifier)?;
         info!("Created {:?} with id {}", person, person_id);
-        Ok((person_id, person.clone()))
+        Ok((person_id, revision, person.clone()))
     }
 
-    pub fn update(&mut self, person_id: PersonId, patch: &PersonPatch) -> Result<Option<PersonData>> {
+    ///
+    /// Creates a person like [insert](Self::insert), unless a person was already created with ``key``.
+    /// In that case, nothing is written and the result of the earlier write is returned.
+    /// A key that was used for other data is rejected with [DomainError::Conflict].
+    ///
+    pub fn insert_idempotent(&mut self, person: &PersonData, key: &str) -> Result<Idempotent<(PersonId, usize, PersonData)>> {
         let tx = self.connection.transaction()?;
-        match PersonTable::select_by_id(&tx, person_id)? {
-            Some(before) => {
-                let after = PersonTable::update(&tx, person_id, &patch)?;
-                // Recompute patch for minimal change set
-                if let Some(patch) = PersonPatch::of(&before, &after) {
-                    self.person_aggr.update(&tx, person_id, &before, &patch)?;
-                    self.location_aggr.update(&tx, person_id, &before, &patch)?;
-                }
-                tx.commit()?;
-                info!("Updated {:?} from {:?}", before, patch);
-                Ok(Some(after))
+        if let Some((person_id, revision, response)) = IdempotencyTable::select(&tx, key)? {
+            // Responses are serialized by this function, errors should not happen, panic accepted
+            let created: PersonData = serde_json::from_str(&response).unwrap();
+            if &created != person {
+                // The rejected person was never created, person_id belongs to the earlier write
+                let error = DomainError::Conflict(format!("Idempotency key {} was already used for another person", key));
+                return Self::reject(tx, None, error)
+            }
+            tx.rollback()?; // There should be no changes, so tx.commit() would also work
+            info!("Replayed creation of {:?} with id {}", created, person_id);
+            return Ok(Idempotent::Replayed((person_id, revision, created)))
+        }
+        // Invalid requests are not stored, so that a corrected retry can use the same key
+        let errors = PersonValidator::validate_data(&tx, None, person)?;
+        if !errors.is_empty() {
+            return Self::reject(tx, None, DomainError::Validation(errors))
+        }
+        let (person_id, revision) = Self::insert_person(&mut self.person_aggr, &mut self.location_aggr, &tx, None, person)?;
+        let response = serde_json::to_string(person).unwrap();
+        IdempotencyTable::insert(&tx, key, self.timestamp.as_secs(), person_id, revision, &response)?;
+        Self::commit_and_notify(tx, &self.notifier)?;
+        info!("Created {:?} with id {}", person, person_id);
+        Ok(Idempotent::Done((person_id, revision, person.clone())))
+    }
+
+    ///
+    /// Applies ``patch`` to a person and returns its revision and its data. If ``precondition`` holds revisions,
+    /// the person is only changed if its revision is one of them, otherwise the write is rejected with
+    /// [DomainError::PreconditionFailed]. The revision of a person is the revision of the last event that changed it.
+    ///
+    pub fn update(&mut self, person_id: PersonId, patch: &PersonPatch, precondition: &Precondition) -> Result<(usize, PersonData)> {
+        let tx = self.connection.transaction()?;
+        let revision = match Self::check_person_revision(&tx, person_id, precondition) {
+            Ok(revision) => revision,
+            Err(error) => return Self::reject(tx, Some(person_id), error)
+        };
+        let errors = PersonValidator::validate_patch(&tx, person_id, patch)?;
+        if !errors.is_empty() {
+            return Self::reject(tx, Some(person_id), DomainError::Validation(errors))
+        }
+        let updated = Self::update_person(&mut self.person_aggr, &mut self.location_aggr, &tx, person_id, revision, patch)?;
+        Self::commit_and_notify(tx, &self.notifier)?;
+        Ok(updated)
+    }
+
+    ///
+    /// Replaces the person with ``person_id`` by ``person``, or creates it with this id if it does not exist.
+    /// Returns whether the person was created, its revision and its data. Unless the write is ``Unconditional``,
+    /// the person must exist and match ``precondition``.
+    ///
+    pub fn replace(&mut self, person_id: PersonId, person: &PersonData, precondition: &Precondition) -> Result<(bool, usize, PersonData)> {
+        let tx = self.connection.transaction()?;
+        let errors = PersonValidator::validate_id(person_id);
+        if !errors.is_empty() {
+            return Self::reject(tx, Some(person_id), DomainError::Validation(errors))
+        }
+        let revision = match Self::check_person_revision(&tx, person_id, precondition) {
+            Ok(revision) => Some(revision),
+            Err(DomainError::NotFound(_)) => None, // Only unconditional writes miss the person
+            Err(error) => return Self::reject(tx, Some(person_id), error)
+        };
+        let errors = PersonValidator::validate_data(&tx, Some(person_id), person)?;
+        if !errors.is_empty() {
+            return Self::reject(tx, Some(person_id), DomainError::Validation(errors))
+        }
+        match revision {
+            Some(revision) => {
+                let before = PersonTable::select_by_id_internal(&tx, person_id)?;
+                // Compute patch for minimal change set
+                let revision = match PersonPatch::of(&before, person) {
+                    Some(patch) => Self::update_person(&mut self.person_aggr, &mut self.location_aggr, &tx, person_id, revision, &patch)?.0,
+                    None => revision // Nothing changed, so there is no event
+                };
+                Self::commit_and_notify(tx, &self.notifier)?;
+                Ok((false, revision, person.clone()))
             },
             None => {
-                tx.rollback()?; // There should be no changes, so tx.commit() would also work
-                warn!("Person {} not found", person_id);
-                Ok(None)
+                PersonSnapshotTable::save(&tx, person_id)?;
+                let (person_id, revision) = Self::insert_person(&mut self.person_aggr, &mut self.location_aggr, &tx, Some(person_id), person)?;
+                Self::commit_and_notify(tx, &self.notifier)?;
+                info!("Created {:?} with id {}", person, person_id);
+                Ok((true, revision, person.clone()))
             }
         }
     }
 
-    pub fn delete(&mut self, person_id: PersonId) -> Result<bool> {
+    /// Deletes a person and returns the revision of the deletion. If ``precondition`` holds revisions,
+    /// the person is only deleted if its revision is one of them.
+    pub fn delete(&mut self, person_id: PersonId, precondition: &Precondition) -> Result<usize> {
         let tx = self.connection.transaction()?;
-        match PersonTable::select_by_id(&tx, person_id)? {
-            Some(before) => {
-                PersonTable::delete(&tx, person_id)?;
-                self.person_aggr.delete(&tx, person_id, &before)?;
-                self.location_aggr.delete(&tx, person_id, &before)?;
-                tx.commit()?;
-                info!("Deleted {:?}", before);
-                Ok(true)
+        if let Err(error) = Self::check_person_revision(&tx, person_id, precondition) {
+            return Self::reject(tx, Some(person_id), error)
+        }
+        let revision = Self::delete_person(&mut self.person_aggr, &mut self.location_aggr, &tx, person_id)?;
+        Self::commit_and_notify(tx, &self.notifier)?;
+        Ok(revision)
+    }
+
+    ///
+    /// Applies all ``operations`` in one transaction. If an operation fails, no operation is applied
+    /// and the error is wrapped into [DomainError::Operation] together with the index of the operation.
+    /// All events of the batch belong to the same commit.
+    ///
+    pub fn apply_batch(&mut self, operations: &[PersonOperation]) -> Result<BatchResult> {
+        let tx = self.connection.transaction()?;
+        let person_revision = RevisionTable::read(&tx, EventType::PERSON)?;
+        let location_revision = RevisionTable::read(&tx, EventType::LOCATION)?;
+        let mut results = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.iter().enumerate() {
+            match Self::apply_operation(&mut self.person_aggr, &mut self.location_aggr, &tx, operation) {
+                Ok(result) => results.push(result),
+                Err(error) => {
+                    tx.rollback()?;
+                    warn!("Batch operation {} failed, rolled back {:?}: {:?}", index, operation, error);
+                    return Err(DomainError::Operation(index, Box::new(error)))
+                }
+            }
+        }
+        let person_revisions = Self::revision_range(&tx, EventType::PERSON, person_revision)?;
+        let location_revisions = Self::revision_range(&tx, EventType::LOCATION, location_revision)?;
+        Self::commit_and_notify(tx, &self.notifier)?;
+        info!("Applied batch of {} operations", operations.len());
+        Ok(BatchResult { results, person_revisions, location_revisions })
+    }
+
+    // Applies one operation of a batch, the transaction is rolled back by the caller if it fails
+    fn apply_operation(person_aggr: &mut PersonAggregator, location_aggr: &mut LocationAggregator, tx: &Transaction, operation: &PersonOperation) -> Result<(PersonId, usize, Option<PersonData>)> {
+        match operation {
+            PersonOperation::Insert { person } => {
+                let errors = PersonValidator::validate_data(tx, None, person)?;
+                if !errors.is_empty() {
+                    return Err(DomainError::Validation(errors))
+                }
+                let (person_id, revision) = Self::insert_person(person_aggr, location_aggr, tx, None, person)?;
+                Ok((person_id, revision, Some(person.clone())))
             },
-            None => {
-                tx.rollback()?; // There should be no changes, so tx.commit() would also work
-                warn!("Person {} not found", person_id);
-                Ok(false)
+            PersonOperation::Patch { id, patch } => {
+                let revision = Self::check_person_revision(tx, *id, &Precondition::Unconditional)?;
+                let errors = PersonValidator::validate_patch(tx, *id, patch)?;
+                if !errors.is_empty() {
+                    return Err(DomainError::Validation(errors))
+                }
+                let (revision, after) = Self::update_person(person_aggr, location_aggr, tx, *id, revision, patch)?;
+                Ok((*id, revision, Some(after)))
+            },
+            PersonOperation::Delete { id } => {
+                Self::check_person_revision(tx, *id, &Precondition::Unconditional)?;
+                Ok((*id, Self::delete_person(person_aggr, location_aggr, tx, *id)?, None))
             }
         }
     }
 
+    // First and last revision written after ``revision``, if any
+    fn revision_range(tx: &Transaction, event_type: EventType, revision: usize) -> Result<Option<(usize, usize)>> {
+        let last_revision = RevisionTable::read(tx, event_type)?;
+        Ok(if last_revision > revision { Some((revision + 1, last_revision)) } else { None })
+    }
+
     pub fn get_persons(&mut self) -> Result<(usize, PersonMap)> {
         let tx = self.connection.transaction()?;
         let result = self.person_aggr.get_all(&tx)?;
@@ -98,6 +342,107 @@ impl AggregatorFacade {
         Ok(result)
     }
 
+    /// Returns the person revision and the persons that match ``filter``, read in one transaction.
+    pub fn get_filtered_persons(&mut self, filter: &PersonFilter) -> Result<(usize, PersonMap)> {
+        let tx = self.connection.transaction()?;
+        let revision = RevisionTable::read(&tx, EventType::PERSON)?;
+        let persons = PersonTable::select_filtered(&tx, filter)?;
+        tx.commit()?;
+        Ok((revision, persons))
+    }
+
+    ///
+    /// Returns at most ``limit`` persons with ids greater than ``after``, in the state of ``snapshot``.
+    /// Without ``snapshot``, the latest snapshot is reused if nothing was written since, otherwise a new snapshot
+    /// of the current persons is taken. All pages of a snapshot are consistent with its revision.
+    /// Returns ``None`` if the snapshot does not exist (anymore).
+    ///
+    pub fn get_person_page(&mut self, snapshot: Option<usize>, after: PersonId, limit: usize) -> Result<Option<PersonPage>> {
+        let tx = self.connection.transaction()?;
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot,
+            None => {
+                let revision = RevisionTable::read(&tx, EventType::PERSON)?;
+                let commit_id = CommitTable::read(&tx)?;
+                let timestamp = self.timestamp.as_secs();
+                match PersonSnapshotTable::reuse(&tx, timestamp, revision, commit_id)? {
+                    Some(snapshot) => snapshot,
+                    None => PersonSnapshotTable::insert(&tx, timestamp, revision, commit_id)?
+                }
+            }
+        };
+        let page = match PersonSnapshotTable::select(&tx, snapshot)? {
+            Some((revision, commit_id)) => {
+                // Read one more person to find out whether there is a next page
+                let mut rows = PersonSnapshotTable::select_page(&tx, snapshot, after, limit + 1)?;
+                let next = if rows.len() > limit {
+                    rows.truncate(limit);
+                    rows.last().map(|(person_id, _)| *person_id)
+                } else {
+                    None
+                };
+                let mut persons = PersonMap::new();
+                for (person_id, person) in rows {
+                    persons.put(person_id, person);
+                }
+                Some(PersonPage { snapshot, revision, commit_id, persons, next })
+            },
+            None => None
+        };
+        tx.commit()?;
+        Ok(page)
+    }
+
+    /// Returns the person revision and the commit id, read in one transaction.
+    pub fn get_person_revision(&mut self) -> Result<(usize, usize)> {
+        let tx = self.connection.transaction()?;
+        let revision = RevisionTable::read(&tx, EventType::PERSON)?;
+        let commit_id = CommitTable::read(&tx)?;
+        tx.commit()?;
+        Ok((revision, commit_id))
+    }
+
+    /// Returns the person revision and the person with ``person_id`` together with its own revision, if any,
+    /// read in one transaction.
+    pub fn get_person(&mut self, person_id: PersonId) -> Result<(usize, Option<(usize, PersonData)>)> {
+        let tx = self.connection.transaction()?;
+        let revision = RevisionTable::read(&tx, EventType::PERSON)?;
+        let person = match PersonTable::select_revision(&tx, person_id)? {
+            Some(person_revision) => PersonTable::select_by_id(&tx, person_id)?.map(|person| (person_revision, person)),
+            None => None
+        };
+        tx.commit()?;
+        Ok((revision, person))
+    }
+
+    /// Returns the location revision and the location of ``city``, if any, read in one transaction.
+    pub fn get_location(&mut self, city: &str) -> Result<(usize, Option<LocationData>)> {
+        let tx = self.connection.transaction()?;
+        let revision = RevisionTable::read(&tx, EventType::LOCATION)?;
+        let location = LocationTable::select_by_name(&tx, city)?;
+        tx.commit()?;
+        Ok((revision, location))
+    }
+
+    ///
+    /// Returns the persons as of ``revision``. They are restored by reverting the events
+    /// after ``revision`` on the current persons, so the events must not be deleted or compacted yet.
+    ///
+    pub fn get_persons_at(&mut self, revision: usize) -> Result<PointInTime<PersonMap>> {
+        let tx = self.connection.transaction()?;
+        let result = Self::restore(&mut self.person_aggr, &tx, revision)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
+    /// Returns the locations as of ``revision``, see [get_persons_at](Self::get_persons_at).
+    pub fn get_locations_at(&mut self, revision: usize) -> Result<PointInTime<LocationMap>> {
+        let tx = self.connection.transaction()?;
+        let result = Self::restore(&mut self.location_aggr, &tx, revision)?;
+        tx.commit()?;
+        Ok(result)
+    }
+
     pub fn get_locations(&mut self) -> Result<(usize, LocationMap)> {
         let tx = self.connection.transaction()?;
         let result = self.location_aggr.get_all(&tx)?;
@@ -105,32 +450,239 @@ impl AggregatorFacade {
         Ok(result)
     }
 
-    pub fn get_events(&mut self, event_type: EventType, from_revision: usize) -> Result<Vec<String>> {
+    /// Returns a page of at most ``limit`` events of ``event_type``, starting at ``from_revision``.
+    pub fn get_events(&mut self, event_type: EventType, from_revision: usize, limit: usize) -> Result<Vec<(usize, String)>> {
+        let tx = self.connection.transaction()?;
+        let events = match event_type {
+            EventType::PERSON => self.person_aggr.get_events(&tx, from_revision, limit),
+            EventType::LOCATION => self.location_aggr.get_events(&tx, from_revision, limit)
+        }?;
+        tx.commit()?;
+        Ok(events)
+    }
+
+    /// Like [get_events](Self::get_events), but each event also carries its creation timestamp.
+    pub fn get_timed_events(&mut self, event_type: EventType, from_revision: usize, limit: usize) -> Result<Vec<(usize, u64, String)>> {
         let tx = self.connection.transaction()?;
         let events = match event_type {
-            EventType::PERSON => self.person_aggr.get_events(&tx, from_revision),
-            EventType::LOCATION => self.location_aggr.get_events(&tx, from_revision)
+            EventType::PERSON => self.person_aggr.get_timed_events(&tx, from_revision, limit),
+            EventType::LOCATION => self.location_aggr.get_timed_events(&tx, from_revision, limit)
         }?;
         tx.commit()?;
         Ok(events)
     }
 
+    ///
+    /// Returns the events of both types written by at most ``limit`` commits, starting at ``from_commit``.
+    /// The events are grouped by commit id, every group holds tuples of event type, revision, and event.
+    ///
+    pub fn get_commits(&mut self, from_commit: usize, limit: usize) -> Result<Vec<CommitEvents>> {
+        let tx = self.connection.transaction()?;
+        let events = CommitTable::read_events(&tx, from_commit, limit)?;
+        tx.commit()?;
+        let mut commits: Vec<CommitEvents> = Vec::new();
+        for (commit_id, event_type, revision, event) in events {
+            match commits.last_mut() {
+                Some((last_id, group)) if *last_id == commit_id => group.push((event_type, revision, event)),
+                _ => commits.push((commit_id, vec![(event_type, revision, event)]))
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Returns the oldest commit id whose events were not yet deleted.
+    pub fn get_first_commit(&mut self) -> Result<usize> {
+        if self.retention == EventRetention::Compact {
+            return Ok(1) // Nothing was lost
+        }
+        let tx = self.connection.transaction()?;
+        let commit_id = match CommitTable::first_commit(&tx)? {
+            Some(commit_id) => commit_id,
+            None => CommitTable::read(&tx)? + 1 // The next commit gets the subsequent id
+        };
+        tx.commit()?;
+        Ok(commit_id)
+    }
+
+    /// Returns the id of the latest commit that wrote events.
+    pub fn get_commit_id(&mut self) -> Result<usize> {
+        let tx = self.connection.transaction()?;
+        let commit_id = CommitTable::read(&tx)?;
+        tx.commit()?;
+        Ok(commit_id)
+    }
+
+    /// Returns the oldest revision of ``event_type`` that was not yet deleted.
+    pub fn get_first_revision(&mut self, event_type: EventType) -> Result<usize> {
+        if self.retention == EventRetention::Compact {
+            return Ok(1) // Nothing was lost
+        }
+        let tx = self.connection.transaction()?;
+        let revision = match event_type {
+            EventType::PERSON => self.person_aggr.get_first_revision(&tx),
+            EventType::LOCATION => self.location_aggr.get_first_revision(&tx)
+        }?;
+        tx.commit()?;
+        Ok(revision)
+    }
+
     pub fn delete_events(&mut self, created_before: Duration) -> Result<usize> {
+        let snapshots_before = self.timestamp.as_secs().saturating_sub(created_before.as_secs());
         let tx = self.connection.transaction()?;
         let mut count = self.person_aggr.delete_events(&tx, created_before)?;
         count += self.location_aggr.delete_events(&tx, created_before)?;
+        Self::delete_snapshots(&tx, snapshots_before)?;
         tx.commit()?;
         if count > 0 {
             info!("Deleted {} outdated events", count);
         }
         Ok(count)
     }
+
+    pub fn compact_events(&mut self, created_before: Duration) -> Result<usize> {
+        let snapshots_before = self.timestamp.as_secs().saturating_sub(created_before.as_secs());
+        let tx = self.connection.transaction()?;
+        let mut count = self.person_aggr.compact_events(&tx, created_before)?;
+        count += self.location_aggr.compact_events(&tx, created_before)?;
+        Self::delete_snapshots(&tx, snapshots_before)?;
+        tx.commit()?;
+        if count > 0 {
+            info!("Compacted {} outdated events", count);
+        }
+        Ok(count)
+    }
+
+    // Snapshots expire together with the events, so consumers can page through them within the retention time.
+    // The same holds for idempotency keys, clients are expected to give up on retries much earlier.
+    fn delete_snapshots(tx: &Transaction, created_before: u64) -> Result<()> {
+        let count = PersonSnapshotTable::delete_before(tx, created_before)?;
+        if count > 0 {
+            info!("Deleted {} outdated snapshots", count);
+        }
+        let count = IdempotencyTable::delete_before(tx, created_before)?;
+        if count > 0 {
+            info!("Deleted {} outdated idempotency keys", count);
+        }
+        Ok(())
+    }
+
+    fn restore<A>(aggregator: &mut A, tx: &Transaction, revision: usize) -> Result<PointInTime<A::Records>>
+        where A: AggregatorTrait, A::Records: Serialize + DeserializeOwned {
+        let (current, records) = aggregator.get_all(tx)?;
+        if revision > current {
+            return Ok(PointInTime::Future(current))
+        }
+        let first = aggregator.get_first_revertible(tx, current)?;
+        if revision < first {
+            return Ok(PointInTime::Purged(first))
+        }
+        // Aggregates and events were serialized by this crate, errors should not happen, panic accepted
+        let mut records = serde_json::to_value(records).unwrap();
+        for undo in aggregator.get_undo_events(tx, revision)? {
+            apply(&mut records, serde_json::from_str(undo.as_str()).unwrap());
+        }
+        Ok(PointInTime::Found(serde_json::from_value(records).unwrap()))
+    }
+
+    // Writes a new person and its events, the person id is assigned by the database unless it is given
+    fn insert_person(person_aggr: &mut PersonAggregator, location_aggr: &mut LocationAggregator, tx: &Transaction, person_id: Option<PersonId>, person: &PersonData) -> Result<(PersonId, usize)> {
+        let person_id = match person_id {
+            Some(person_id) => PersonTable::insert_with_id(tx, person_id, person)?,
+            None => PersonTable::insert(tx, person)?
+        };
+        person_aggr.insert(tx, person_id, person)?;
+        location_aggr.insert(tx, person_id, person)?;
+        let revision = Self::update_person_revision(tx, person_id)?;
+        Ok((person_id, revision))
+    }
+
+    // Requires that the person exists, its revision is kept if the patch does not change anything
+    fn update_person(person_aggr: &mut PersonAggregator, location_aggr: &mut LocationAggregator, tx: &Transaction, person_id: PersonId, revision: usize, patch: &PersonPatch) -> Result<(usize, PersonData)> {
+        let before = PersonTable::select_by_id_internal(tx, person_id)?;
+        PersonSnapshotTable::save(tx, person_id)?;
+        let after = PersonTable::update(tx, person_id, patch)?;
+        // Recompute patch for minimal change set
+        let revision = match PersonPatch::of(&before, &after) {
+            Some(patch) => {
+                person_aggr.update(tx, person_id, &before, &patch)?;
+                location_aggr.update(tx, person_id, &before, &patch)?;
+                Self::update_person_revision(tx, person_id)?
+            },
+            None => revision // Nothing changed, so there is no event
+        };
+        info!("Updated {:?} from {:?}", before, patch);
+        Ok((revision, after))
+    }
+
+    // Requires that the person exists, returns the revision of the deletion
+    fn delete_person(person_aggr: &mut PersonAggregator, location_aggr: &mut LocationAggregator, tx: &Transaction, person_id: PersonId) -> Result<usize> {
+        let before = PersonTable::select_by_id_internal(tx, person_id)?;
+        PersonSnapshotTable::save(tx, person_id)?;
+        PersonTable::delete(tx, person_id)?;
+        person_aggr.delete(tx, person_id, &before)?;
+        location_aggr.delete(tx, person_id, &before)?;
+        info!("Deleted {:?}", before);
+        Ok(RevisionTable::read(tx, EventType::PERSON)?)
+    }
+
+    // The person events were just written, so the person revision is the revision of the person
+    fn update_person_revision(tx: &Transaction, person_id: PersonId) -> Result<usize> {
+        let revision = RevisionTable::read(tx, EventType::PERSON)?;
+        PersonTable::update_revision(tx, person_id, revision)?;
+        Ok(revision)
+    }
+
+    // Returns the revision of the person if it exists and matches the precondition.
+    // Preconditions cannot match a person that does not exist (RFC 9110), so they fail rather than not finding it.
+    fn check_person_revision(tx: &Transaction, person_id: PersonId, precondition: &Precondition) -> Result<usize> {
+        match (PersonTable::select_revision(tx, person_id)?, precondition) {
+            (None, Precondition::Unconditional) => Err(DomainError::NotFound(format!("Person {} not found", person_id))),
+            (None, _) => Err(DomainError::PreconditionFailed(format!("Person {} does not exist", person_id), None)),
+            (Some(revision), Precondition::Revisions(expected)) if !expected.contains(&revision) => {
+                let message = format!("Person {} was changed, its revision is {}", person_id, revision);
+                Err(DomainError::PreconditionFailed(message, Some(revision)))
+            },
+            (Some(revision), _) => Ok(revision)
+        }
+    }
+
+    // Rolls back a write that was refused
+    fn reject<T>(tx: Transaction, person_id: Option<PersonId>, error: DomainError) -> Result<T> {
+        tx.rollback()?; // There should be no changes, so tx.commit() would also work
+        match person_id {
+            Some(person_id) => warn!("Person {} was not written: {:?}", person_id, error),
+            None => warn!("Person was not written: {:?}", error)
+        }
+        Err(error)
+    }
+
+    // Associated function rather than method, because the transaction mutably borrows the connection
+    // Before committing, the events written by the transaction are annotated with the next commit id
+    fn commit_and_notify(tx: Transaction, notifier: &RevisionNotifier) -> Result<()> {
+        let mut commit_id = CommitTable::read(&tx)?;
+        let count = PersonEventTable::assign_commit(&tx, commit_id + 1)? + LocationEventTable::assign_commit(&tx, commit_id + 1)?;
+        if count > 0 {
+            commit_id += 1;
+            CommitTable::upsert(&tx, commit_id)?;
+        }
+        let person_revision = RevisionTable::read(&tx, EventType::PERSON)?;
+        let location_revision = RevisionTable::read(&tx, EventType::LOCATION)?;
+        tx.commit()?;
+        notifier.notify(EventType::PERSON, person_revision);
+        notifier.notify(EventType::LOCATION, location_revision);
+        notifier.notify_commit(commit_id);
+        Ok(())
+    }
 }
 
 // Implementation of the task for the deletion scheduler
-impl DeletionTask<rusqlite::Error> for AggregatorFacade {
+impl DeletionTask<DomainError> for AggregatorFacade {
     fn delete(&mut self, created_before: Duration) -> Result<()> {
-        match self.delete_events(created_before) {
+        let result = match self.retention {
+            EventRetention::Delete => self.delete_events(created_before),
+            EventRetention::Compact => self.compact_events(created_before)
+        };
+        match result {
             Ok(_) => Ok(()),
             Err(e) => Err(e)
         }
@@ -139,15 +691,24 @@ impl DeletionTask<rusqlite::Error> for AggregatorFacade {
 
 #[cfg(test)]
 mod tests {
-    use crate::aggregator::aggregator_facade::AggregatorFacade;
+    use std::time::Duration;
+    use serde_json::Value;
+    use crate::aggregator::aggregator_facade::{AggregatorFacade, BatchResult, EventRetention, Idempotent, PersonPage, PointInTime};
     use crate::aggregator::person_aggregator::tests::compare_events;
+    use crate::database::event_table::PersonEventTable;
     use crate::domain::event_type::EventType;
     use crate::domain::location_data::LocationData;
     use crate::domain::location_map::LocationMap;
     use crate::domain::person_data::PersonData;
+    use crate::domain::person_filter::PersonFilter;
     use crate::domain::person_id::PersonId;
     use crate::domain::person_map::PersonMap;
+    use crate::domain::person_operation::PersonOperation;
     use crate::domain::person_patch::PersonPatch;
+    use crate::domain::precondition::Precondition;
+    use crate::error::DomainError;
+    use crate::util::deletion_scheduler::DeletionTask;
+    use crate::util::merge_patch::apply;
     use crate::util::patch::Patch;
 
     //
@@ -161,23 +722,161 @@ mod tests {
         let person = PersonData::new("Ann", None, None);
         let person_res = aggregator.insert(&person);
         assert!(person_res.is_ok());
-        let (person_id, person_data) = person_res.unwrap();
+        let (person_id, revision, person_data) = person_res.unwrap();
         assert_eq!(person_id, PersonId::from(1));
+        assert_eq!(revision, 1);
         assert_eq!(person_data, person);
     }
 
+    #[test]
+    fn test_insert_idempotent() {
+        let mut aggregator = create_aggregator();
+
+        let person = PersonData::new("Ann", None, None);
+        let person_res = aggregator.insert_idempotent(&person, "abc");
+        assert_eq!(person_res.unwrap(), Idempotent::Done((PersonId::from(1), 1, person.clone())));
+        let person_res = aggregator.insert_idempotent(&person, "abc");
+        assert_eq!(person_res.unwrap(), Idempotent::Replayed((PersonId::from(1), 1, person.clone())));
+        let person_res = aggregator.insert_idempotent(&PersonData::new("Bob", None, None), "abc");
+        assert!(matches!(person_res.unwrap_err(), DomainError::Conflict(_)));
+        assert_eq!(aggregator.get_persons().unwrap().0, 1);
+
+        let person_res = aggregator.insert_idempotent(&person, "xyz");
+        assert_eq!(person_res.unwrap(), Idempotent::Done((PersonId::from(2), 2, person)));
+    }
+
     #[test]
     fn test_update() {
         let mut aggregator = create_aggregator();
 
         let person = PersonData::new("Ann", None, None);
-        let patch = PersonPatch::new(Some("Bob"), Patch::Value("here"), Patch::Value(PersonId::from(123)));
+        let patch = PersonPatch::new(Some("Bob"), Patch::Value("here"), Patch::Value(PersonId::from(2)));
         assert!(aggregator.insert(&person).is_ok());
-        let person_res = aggregator.update(PersonId::from(1), &patch);
+        assert!(aggregator.insert(&person).is_ok());
+        let person_res = aggregator.update(PersonId::from(1), &patch, &Precondition::Unconditional);
         assert!(person_res.is_ok());
 
-        let person_ref = PersonData::new("Bob", Some("here"), Some(PersonId::from(123)));
-        assert_eq!(person_res.unwrap(), Some(person_ref));
+        let person_ref = PersonData::new("Bob", Some("here"), Some(PersonId::from(2)));
+        assert_eq!(person_res.unwrap(), (3, person_ref));
+    }
+
+    #[test]
+    fn test_update_invalid() {
+        let mut aggregator = create_aggregator();
+
+        assert!(aggregator.insert(&PersonData::new("Ann", None, None)).is_ok());
+        let patch = PersonPatch::new(Some(" "), Patch::Absent, Patch::Value(PersonId::from(123)));
+        let codes = match aggregator.update(PersonId::from(1), &patch, &Precondition::Unconditional).unwrap_err() {
+            DomainError::Validation(errors) => errors.iter().map(|error| error.code).collect(),
+            _ => Vec::new()
+        };
+        assert_eq!(codes, vec!["blank", "not_found"]);
+        let person_res = aggregator.insert(&PersonData::new("Bob", None, Some(PersonId::from(3))));
+        assert!(matches!(person_res.unwrap_err(), DomainError::Validation(errors) if errors.len() == 1));
+        assert_eq!(aggregator.get_persons().unwrap().0, 1);
+    }
+
+    #[test]
+    fn test_update_conditional() {
+        let mut aggregator = create_aggregator();
+
+        assert!(aggregator.insert(&PersonData::new("Ann", None, None)).is_ok());
+        assert!(aggregator.insert(&PersonData::new("Bob", None, None)).is_ok());
+        let patch = PersonPatch::new(None, Patch::Value("here"), Patch::Absent);
+        let person_res = aggregator.update(PersonId::from(1), &patch, &Precondition::Revisions(vec![2]));
+        assert!(matches!(person_res.unwrap_err(), DomainError::PreconditionFailed(_, Some(1))));
+        let person_res = aggregator.update(PersonId::from(3), &patch, &Precondition::Exists);
+        assert!(matches!(person_res.unwrap_err(), DomainError::PreconditionFailed(_, None)));
+        let person_res = aggregator.update(PersonId::from(1), &patch, &Precondition::Revisions(vec![0, 1]));
+        assert_eq!(person_res.unwrap(), (3, PersonData::new("Ann", Some("here"), None)));
+
+        // Patches without changes keep the revision
+        let person_res = aggregator.update(PersonId::from(1), &patch, &Precondition::Revisions(vec![3]));
+        assert_eq!(person_res.unwrap(), (3, PersonData::new("Ann", Some("here"), None)));
+        assert_eq!(aggregator.get_person(PersonId::from(2)).unwrap(), (3, Some((2, PersonData::new("Bob", None, None)))));
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut aggregator = create_aggregator();
+
+        let person = PersonData::new("Ann", Some("here"), None);
+        let person_res = aggregator.replace(PersonId::from(5), &person, &Precondition::Revisions(vec![0]));
+        assert!(matches!(person_res.unwrap_err(), DomainError::PreconditionFailed(_, None)));
+        let person_res = aggregator.replace(PersonId::from(5), &person, &Precondition::Exists);
+        assert!(matches!(person_res.unwrap_err(), DomainError::PreconditionFailed(_, None)));
+        let person_res = aggregator.replace(PersonId::from(5), &person, &Precondition::Unconditional);
+        assert_eq!(person_res.unwrap(), (true, 1, person.clone()));
+        let person_res = aggregator.replace(PersonId::from(u64::MAX), &person, &Precondition::Unconditional);
+        assert!(matches!(person_res.unwrap_err(), DomainError::Validation(_)));
+
+        let person = PersonData::new("Ann", None, Some(PersonId::from(5)));
+        let person_res = aggregator.replace(PersonId::from(5), &person, &Precondition::Revisions(vec![1]));
+        assert!(matches!(person_res.unwrap_err(), DomainError::Validation(_)));
+        assert!(aggregator.insert(&PersonData::new("Bob", None, None)).is_ok()); // Gets id 6
+        let person = PersonData::new("Ann", None, Some(PersonId::from(6)));
+        let person_res = aggregator.replace(PersonId::from(5), &person, &Precondition::Revisions(vec![1]));
+        assert_eq!(person_res.unwrap(), (false, 3, person.clone()));
+        let person_res = aggregator.replace(PersonId::from(5), &person, &Precondition::Revisions(vec![1]));
+        assert!(matches!(person_res.unwrap_err(), DomainError::PreconditionFailed(_, Some(3))));
+
+        // Only the changed fields are emitted
+        let events = aggregator.get_events(EventType::PERSON, 1, 10).unwrap();
+        assert_eq!(events[2], (3, String::from(r#"{"5":{"city":null,"spouse":6}}"#)));
+        let events = aggregator.get_events(EventType::LOCATION, 1, 10).unwrap();
+        assert_eq!(events[1], (2, String::from(r#"{"here":null}"#)));
+    }
+
+    #[test]
+    fn test_apply_batch() {
+        let mut aggregator = create_aggregator();
+        assert!(aggregator.insert(&PersonData::new("Ann", Some("here"), None)).is_ok());
+
+        let operations = [
+            PersonOperation::Insert { person: PersonData::new("Bob", None, None) },
+            PersonOperation::Patch { id: PersonId::from(2), patch: PersonPatch::new(None, Patch::Value("there"), Patch::Absent) },
+            PersonOperation::Delete { id: PersonId::from(1) }
+        ];
+        let batch_res = aggregator.apply_batch(&operations);
+        assert_eq!(batch_res.unwrap(), BatchResult {
+            results: vec![
+                (PersonId::from(2), 2, Some(PersonData::new("Bob", None, None))),
+                (PersonId::from(2), 3, Some(PersonData::new("Bob", Some("there"), None))),
+                (PersonId::from(1), 4, None)
+            ],
+            person_revisions: Some((2, 4)),
+            location_revisions: Some((2, 3))
+        });
+        assert_eq!(aggregator.get_commit_id().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_apply_batch_rollback() {
+        let mut aggregator = create_aggregator();
+
+        let operations = [
+            PersonOperation::Insert { person: PersonData::new("Ann", None, None) },
+            PersonOperation::Delete { id: PersonId::from(2) }
+        ];
+        let error = DomainError::Operation(1, Box::new(DomainError::NotFound(String::from("Person 2 not found"))));
+        assert_eq!(aggregator.apply_batch(&operations).unwrap_err(), error);
+        let operations = [
+            PersonOperation::Insert { person: PersonData::new("Ann", None, None) },
+            PersonOperation::Patch { id: PersonId::from(1), patch: PersonPatch::new(None, Patch::Absent, Patch::Absent) }
+        ];
+        assert!(matches!(aggregator.apply_batch(&operations).unwrap_err(), DomainError::Operation(1, cause) if matches!(*cause, DomainError::Validation(_))));
+        // Spouses created earlier in the same batch are found
+        let operations = [
+            PersonOperation::Insert { person: PersonData::new("Ann", None, None) },
+            PersonOperation::Insert { person: PersonData::new("Bob", None, Some(PersonId::from(1))) },
+            PersonOperation::Delete { id: PersonId::from(1) },
+            PersonOperation::Insert { person: PersonData::new("Cat", None, Some(PersonId::from(1))) }
+        ];
+        assert!(matches!(aggregator.apply_batch(&operations).unwrap_err(), DomainError::Operation(3, cause) if matches!(*cause, DomainError::Validation(_))));
+        assert_eq!(aggregator.get_persons().unwrap(), (0, PersonMap::new()));
+
+        let batch_res = aggregator.apply_batch(&[]);
+        assert_eq!(batch_res.unwrap(), BatchResult { results: vec![], person_revisions: None, location_revisions: None });
     }
 
     #[test]
@@ -185,9 +884,10 @@ mod tests {
         let mut aggregator = create_aggregator();
 
         let person_update = PersonPatch::new(Some("Bob"), Patch::Value("nowhere"), Patch::Null);
-        let person_res = aggregator.update(PersonId::from(1), &person_update);
-        assert!(person_res.is_ok());
-        assert_eq!(person_res.unwrap(), None);
+        let person_res = aggregator.update(PersonId::from(1), &person_update, &Precondition::Unconditional);
+        assert_eq!(person_res.unwrap_err(), DomainError::NotFound(String::from("Person 1 not found")));
+        let person_res = aggregator.update(PersonId::from(1), &person_update, &Precondition::Revisions(vec![1]));
+        assert!(matches!(person_res.unwrap_err(), DomainError::PreconditionFailed(_, None)));
     }
 
     #[test]
@@ -196,18 +896,28 @@ mod tests {
 
         let person = PersonData::new("Ann", None, None);
         assert!(aggregator.insert(&person).is_ok());
-        let person_res = aggregator.delete(PersonId::from(1));
+        let person_res = aggregator.delete(PersonId::from(1), &Precondition::Unconditional);
         assert!(person_res.is_ok());
-        assert_eq!(person_res.unwrap(), true);
+        assert_eq!(person_res.unwrap(), 2);
+    }
+
+    #[test]
+    fn test_delete_conditional() {
+        let mut aggregator = create_aggregator();
+
+        assert!(aggregator.insert(&PersonData::new("Ann", None, None)).is_ok());
+        assert!(matches!(aggregator.delete(PersonId::from(1), &Precondition::Revisions(vec![])).unwrap_err(), DomainError::PreconditionFailed(_, Some(1))));
+        assert_eq!(aggregator.delete(PersonId::from(1), &Precondition::Revisions(vec![1])).unwrap(), 2);
     }
 
     #[test]
     fn test_delete_missing() {
         let mut aggregator = create_aggregator();
 
-        let person_res = aggregator.delete(PersonId::from(1));
-        assert!(person_res.is_ok());
-        assert_eq!(person_res.unwrap(), false);
+        let person_res = aggregator.delete(PersonId::from(1), &Precondition::Unconditional);
+        assert_eq!(person_res.unwrap_err(), DomainError::NotFound(String::from("Person 1 not found")));
+        let person_res = aggregator.delete(PersonId::from(1), &Precondition::Exists);
+        assert!(matches!(person_res.unwrap_err(), DomainError::PreconditionFailed(_, None)));
     }
 
     //
@@ -240,13 +950,112 @@ mod tests {
         assert_eq!(persons_res.unwrap(), person_ref);
     }
 
+    #[test]
+    fn test_get_person() {
+        let mut aggregator = create_aggregator();
+        assert_eq!(aggregator.get_person(PersonId::from(1)).unwrap(), (0, None));
+
+        let person = PersonData::new("Ann", Some("here"), None);
+        assert!(aggregator.insert(&person).is_ok());
+        assert!(aggregator.insert(&person).is_ok());
+        assert_eq!(aggregator.get_person(PersonId::from(1)).unwrap(), (2, Some((1, person))));
+        assert_eq!(aggregator.get_person(PersonId::from(3)).unwrap(), (2, None));
+    }
+
+    #[test]
+    fn test_get_filtered_persons() {
+        let mut aggregator = create_aggregator();
+        assert!(aggregator.insert(&PersonData::new("Ann", Some("here"), None)).is_ok());
+        assert!(aggregator.insert(&PersonData::new("Bob", Some("there"), None)).is_ok());
+        let filter = PersonFilter { city: Some(String::from("here")), ..PersonFilter::default() };
+        let mut persons = PersonMap::new();
+        persons.put(PersonId::from(1), PersonData::new("Ann", Some("here"), None));
+        assert_eq!(aggregator.get_filtered_persons(&filter).unwrap(), (2, persons));
+    }
+
+    #[test]
+    fn test_get_persons_at() {
+        let mut aggregator = create_aggregator();
+        assert!(aggregator.insert(&PersonData::new("Ann", Some("here"), None)).is_ok());
+        assert!(aggregator.insert(&PersonData::new("Bob", None, None)).is_ok());
+        let patch = PersonPatch::new(Some("Cam"), Patch::Null, Patch::Value(PersonId::from(2)));
+        assert!(aggregator.update(PersonId::from(1), &patch, &Precondition::Unconditional).is_ok());
+        assert!(aggregator.delete(PersonId::from(2), &Precondition::Unconditional).is_ok());
+
+        let mut persons = PersonMap::new();
+        persons.put(PersonId::from(1), PersonData::new("Ann", Some("here"), None));
+        persons.put(PersonId::from(2), PersonData::new("Bob", None, None));
+        assert_eq!(aggregator.get_persons_at(2).unwrap(), PointInTime::Found(persons));
+        assert_eq!(aggregator.get_persons_at(0).unwrap(), PointInTime::Found(PersonMap::new()));
+        assert_eq!(aggregator.get_persons_at(4).unwrap(), PointInTime::Found(aggregator.get_persons().unwrap().1));
+        assert_eq!(aggregator.get_persons_at(5).unwrap(), PointInTime::Future(4));
+    }
+
+    #[test]
+    fn test_get_locations_at() {
+        let mut aggregator = create_aggregator();
+        assert!(aggregator.insert(&PersonData::new("Ann", Some("here"), None)).is_ok());
+        let patch = PersonPatch::new(None, Patch::Value("there"), Patch::Absent);
+        assert!(aggregator.update(PersonId::from(1), &patch, &Precondition::Unconditional).is_ok());
+
+        let mut locations = LocationMap::new();
+        locations.put("here", LocationData::new(1, 0));
+        assert_eq!(aggregator.get_locations_at(1).unwrap(), PointInTime::Found(locations));
+    }
+
+    #[test]
+    fn test_get_person_page() {
+        let mut aggregator = create_aggregator();
+        for name in ["Ann", "Bob", "Cat"] {
+            assert!(aggregator.insert(&PersonData::new(name, None, None)).is_ok());
+        }
+        let page = aggregator.get_person_page(None, PersonId::from(0), 2).unwrap().unwrap();
+        let mut persons = PersonMap::new();
+        persons.put(PersonId::from(1), PersonData::new("Ann", None, None));
+        persons.put(PersonId::from(2), PersonData::new("Bob", None, None));
+        assert_eq!(page, PersonPage { snapshot: 1, revision: 3, commit_id: 3, persons, next: Some(PersonId::from(2)) });
+
+        // Changes after the first page must not show up on the next page
+        let patch = PersonPatch::new(None, Patch::Value("here"), Patch::Absent);
+        assert!(aggregator.update(PersonId::from(3), &patch, &Precondition::Unconditional).is_ok());
+        assert!(aggregator.insert(&PersonData::new("Dan", None, None)).is_ok());
+        let page = aggregator.get_person_page(Some(1), PersonId::from(2), 2).unwrap().unwrap();
+        let mut persons = PersonMap::new();
+        persons.put(PersonId::from(3), PersonData::new("Cat", None, None));
+        assert_eq!(page, PersonPage { snapshot: 1, revision: 3, commit_id: 3, persons, next: None });
+
+        assert_eq!(aggregator.get_person_page(Some(2), PersonId::from(0), 2).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_person_page_reuses_snapshot() {
+        let mut aggregator = create_aggregator();
+        assert!(aggregator.insert(&PersonData::new("Ann", None, None)).is_ok());
+        let snapshot = |aggregator: &mut AggregatorFacade| aggregator.get_person_page(None, PersonId::from(0), 2).unwrap().unwrap().snapshot;
+        assert_eq!(snapshot(&mut aggregator), 1);
+        assert_eq!(snapshot(&mut aggregator), 1);
+        assert!(aggregator.insert(&PersonData::new("Bob", None, None)).is_ok());
+        assert_eq!(snapshot(&mut aggregator), 2);
+    }
+
+    #[test]
+    fn test_get_location() {
+        let mut aggregator = create_aggregator();
+        assert_eq!(aggregator.get_location("here").unwrap(), (0, None));
+
+        let person = PersonData::new("Ann", Some("here"), None);
+        assert!(aggregator.insert(&person).is_ok());
+        assert_eq!(aggregator.get_location("here").unwrap(), (1, Some(LocationData::new(1, 0))));
+        assert_eq!(aggregator.get_location("there").unwrap(), (1, None));
+    }
+
     #[test]
     fn test_get_locations() {
         let mut aggregator = create_aggregator();
 
-        let person1 = PersonData::new("Ann", Some("here"), Some(PersonId::from(123)));
+        let person1 = PersonData::new("Ann", Some("here"), None);
         let person2 = PersonData::new("Bob", Some("there"), None);
-        let person3 = PersonData::new("Cam", Some("here"), None);
+        let person3 = PersonData::new("Cam", Some("here"), Some(PersonId::from(1)));
         assert!(aggregator.insert(&person1).is_ok());
         assert!(aggregator.insert(&person2).is_ok());
         assert!(aggregator.insert(&person3).is_ok());
@@ -265,25 +1074,148 @@ mod tests {
     fn test_get_events() {
         let mut aggregator = create_aggregator();
 
-        let person1 = PersonData::new("Ann", Some("here"), Some(PersonId::from(123)));
+        let person1 = PersonData::new("Ann", Some("here"), None);
         let person2 = PersonData::new("Bob", Some("there"), None);
-        let person3 = PersonData::new("Cam", Some("here"), None);
+        let person3 = PersonData::new("Cam", Some("here"), Some(PersonId::from(1)));
         assert!(aggregator.insert(&person1).is_ok());
         assert!(aggregator.insert(&person2).is_ok());
         assert!(aggregator.insert(&person3).is_ok());
 
-        let events = aggregator.get_events(EventType::PERSON, 0);
+        let events = aggregator.get_events(EventType::PERSON, 0, 10);
         compare_events(events, &[
-            r#"{"1":{"name":"Ann","city":"here","spouse":123}}"#,
+            r#"{"1":{"name":"Ann","city":"here"}}"#,
             r#"{"2":{"name":"Bob","city":"there"}}"#,
-            r#"{"3":{"name":"Cam","city":"here"}}"#
+            r#"{"3":{"name":"Cam","city":"here","spouse":1}}"#
         ]);
-        let events = aggregator.get_events(EventType::LOCATION, 0);
+        let events = aggregator.get_events(EventType::LOCATION, 0, 10);
         compare_events(events, &[
-            r#"{"here":{"total":1,"married":1}}"#,
+            r#"{"here":{"total":1,"married":0}}"#,
             r#"{"there":{"total":1,"married":0}}"#,
-            r#"{"here":{"total":2}}"#
+            r#"{"here":{"total":2,"married":1}}"#
+        ]);
+        let events = aggregator.get_events(EventType::LOCATION, 2, 1);
+        compare_events(events, &[r#"{"there":{"total":1,"married":0}}"#]);
+    }
+
+    #[test]
+    fn test_get_timed_events() {
+        let mut aggregator = create_aggregator();
+        let person = PersonData::new("Ann", None, None);
+        assert!(aggregator.insert(&person).is_ok());
+
+        let events = aggregator.get_timed_events(EventType::PERSON, 1, 10);
+        assert!(events.is_ok());
+        let events = events.unwrap();
+        assert_eq!(events.len(), 1);
+        let (revision, time, event) = &events[0];
+        assert_eq!(*revision, 1);
+        assert!(*time > 0);
+        assert_eq!(event, r#"{"1":{"name":"Ann"}}"#);
+        assert!(aggregator.get_timed_events(EventType::LOCATION, 1, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let mut aggregator = create_aggregator();
+        let person_rx = aggregator.subscribe(EventType::PERSON);
+        let location_rx = aggregator.subscribe(EventType::LOCATION);
+
+        let person = PersonData::new("Ann", None, None);
+        assert!(aggregator.insert(&person).is_ok());
+        assert!(person_rx.has_changed().unwrap());
+        assert_eq!(*person_rx.borrow(), 1);
+        assert!(!location_rx.has_changed().unwrap()); // No location event for persons without city
+
+        let patch = PersonPatch::new(None, Patch::Value("here"), Patch::Absent);
+        assert!(aggregator.update(PersonId::from(1), &patch, &Precondition::Unconditional).is_ok());
+        assert_eq!(*person_rx.borrow(), 2);
+        assert!(location_rx.has_changed().unwrap());
+        assert_eq!(*location_rx.borrow(), 1);
+    }
+
+    #[test]
+    fn test_get_commits() {
+        let mut aggregator = create_aggregator();
+        let commit_rx = aggregator.subscribe_commits();
+        let person = PersonData::new("Ann", Some("here"), None);
+        assert!(aggregator.insert(&person).is_ok());
+        let patch = PersonPatch::new(None, Patch::Value("there"), Patch::Absent);
+        assert!(aggregator.update(PersonId::from(1), &patch, &Precondition::Unconditional).is_ok());
+        assert!(aggregator.update(PersonId::from(1), &patch, &Precondition::Unconditional).is_ok()); // No change, no commit id
+        assert_eq!(*commit_rx.borrow(), 2);
+        assert_eq!(aggregator.get_commit_id().unwrap(), 2);
+        assert_eq!(aggregator.get_first_commit().unwrap(), 1);
+
+        let commits = aggregator.get_commits(2, 10);
+        assert!(commits.is_ok());
+        assert_eq!(commits.unwrap(), vec![
+            (2, vec![
+                (EventType::PERSON, 2, String::from(r#"{"1":{"city":"there"}}"#)),
+                (EventType::LOCATION, 2, String::from(r#"{"here":null}"#)),
+                (EventType::LOCATION, 3, String::from(r#"{"there":{"total":1,"married":0}}"#))
+            ])
         ]);
+        assert_eq!(aggregator.get_commits(1, 1).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_compact_retention() {
+        let aggregator = AggregatorFacade::with_retention(":memory:", EventRetention::Compact);
+        assert!(aggregator.is_ok());
+        let mut aggregator = aggregator.unwrap();
+        let person = PersonData::new("Ann", Some("here"), None);
+        assert!(aggregator.insert(&person).is_ok());
+        assert!(aggregator.delete(PersonId::from(1), &Precondition::Unconditional).is_ok());
+        assert!(DeletionTask::delete(&mut aggregator, Duration::from_secs(60)).is_ok());
+        assert_eq!(aggregator.get_first_revision(EventType::PERSON).unwrap(), 1);
+        assert_eq!(aggregator.get_first_revision(EventType::LOCATION).unwrap(), 1);
+        assert_eq!(aggregator.get_first_commit().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_retention_from_str() {
+        assert_eq!("delete".parse::<EventRetention>(), Ok(EventRetention::Delete));
+        assert_eq!(" Compact".parse::<EventRetention>(), Ok(EventRetention::Compact));
+        assert!("keep".parse::<EventRetention>().is_err());
+    }
+
+    #[test]
+    fn test_retention_before_epoch() {
+        // Retention times reaching before the epoch keep all events
+        let mut aggregator = AggregatorFacade::new(":memory:").unwrap();
+        assert!(aggregator.insert(&PersonData::new("Ann", Some("here"), None)).is_ok());
+        assert_eq!(aggregator.delete_events(Duration::from_secs(u64::MAX)), Ok(0));
+        assert_eq!(aggregator.compact_events(Duration::from_secs(u64::MAX)), Ok(0));
+        assert_eq!(aggregator.get_first_revision(EventType::PERSON).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_compact_recreated_person() {
+        let mut aggregator = AggregatorFacade::with_retention(":memory:", EventRetention::Compact).unwrap();
+        assert!(aggregator.replace(PersonId::from(5), &PersonData::new("Ann", Some("here"), None), &Precondition::Unconditional).is_ok());
+        let before: Value = serde_json::to_value(aggregator.get_persons().unwrap().1).unwrap();
+        assert!(aggregator.delete(PersonId::from(5), &Precondition::Unconditional).is_ok());
+        assert!(aggregator.replace(PersonId::from(5), &PersonData::new("Bob", None, None), &Precondition::Unconditional).is_ok());
+        // Age all events, so that they are compacted
+        let stmt = format!("UPDATE {} SET time = 0", PersonEventTable::name());
+        assert!(aggregator.connection.execute(stmt.as_str(), []).is_ok());
+        assert!(DeletionTask::delete(&mut aggregator, Duration::from_secs(60)).is_ok());
+
+        // A consumer that resumes after the first revision converges to the re-created person
+        let events = aggregator.get_events(EventType::PERSON, 2, 10).unwrap();
+        assert_eq!(events, vec![(2, String::from(r#"{"5":null}"#)), (3, String::from(r#"{"5":{"name":"Bob"}}"#))]);
+        let mut persons = before;
+        for (_, event) in events {
+            apply(&mut persons, serde_json::from_str(&event).unwrap());
+        }
+        assert_eq!(persons, serde_json::to_value(aggregator.get_persons().unwrap().1).unwrap());
+    }
+
+    #[test]
+    fn test_get_first_commit_empty() {
+        let mut aggregator = create_aggregator();
+        assert_eq!(aggregator.get_first_commit().unwrap(), 1);
+        assert_eq!(aggregator.get_commit_id().unwrap(), 0);
     }
 
     //
@@ -15,6 +15,11 @@ pub trait AggregatorTrait {
 
     fn get_all(&mut self, tx: &Transaction) -> Result<(usize, Self::Records)>;
 
-    fn get_events(&mut self, tx: &Transaction, from_revision: usize) -> Result<Vec<String>>;
+    fn get_events(&mut self, tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, String)>>;
+    fn get_timed_events(&mut self, tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, u64, String)>>;
+    fn get_first_revision(&mut self, tx: &Transaction) -> Result<usize>;
+    fn get_undo_events(&mut self, tx: &Transaction, revision: usize) -> Result<Vec<String>>;
+    fn get_first_revertible(&mut self, tx: &Transaction, revision: usize) -> Result<usize>;
     fn delete_events(&mut self, tx: &Transaction, created_before: Duration) -> Result<usize>;
+    fn compact_events(&mut self, tx: &Transaction, created_before: Duration) -> Result<usize>;
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 use std::time::Duration;
 use rusqlite::{Connection, Result, Transaction};
+use serde_json::Value;
 use crate::aggregator::aggregator_trait::AggregatorTrait;
 use crate::database::event_table::LocationEventTable;
 use crate::database::location_table::LocationTable;
@@ -13,6 +14,7 @@ use crate::domain::person_data::PersonData;
 use crate::domain::person_id::PersonId;
 use crate::domain::person_patch::PersonPatch;
 use crate::util::patch::Patch;
+use crate::util::merge_patch::invert_event;
 use crate::util::timestamp::{BoxedTimestamp, UnixTimestamp};
 
 ///
@@ -45,10 +47,11 @@ impl LocationAggregator {
     /// writes it to database, and increments the revision number.
     ///
     fn upsert(&mut self, tx: &Transaction, city: &str, mut data: LocationData, patch: LocationPatch) -> Result<()> {
+        let before = Self::existing(&data);
         data.apply_patch(&patch);
         LocationTable::upsert(tx, city, &data)?;
         let event = LocationEvent::new(city, Some(patch));
-        self.write_event_and_revision(tx, event)
+        self.write_event_and_revision(tx, city, before, event)
     }
 
     ///
@@ -61,6 +64,7 @@ impl LocationAggregator {
     fn update_or_delete(&mut self, tx: &Transaction, city: &str, mut data: LocationData, patch: LocationPatch) -> Result<()> {
         // If after an update or delete the attribute "total" is 0, then delete the corresponding
         // location record and write an event that indicates deletion, i.e. { <location>: null }.
+        let before = Self::existing(&data);
         let event : LocationEvent;
         if patch.total.is_some() && patch.total.unwrap() == 0 {
             LocationTable::delete(tx, city)?;
@@ -70,18 +74,24 @@ impl LocationAggregator {
             LocationTable::upsert(tx, city, &data)?;
             event = LocationEvent::new(city, Some(patch));
         }
-        self.write_event_and_revision(tx, event)
+        self.write_event_and_revision(tx, city, before, event)
     }
 
-    fn write_event_and_revision(&mut self, tx: &Transaction, event: LocationEvent) -> Result<()> {
-        let event = Self::stringify(event);
-        let timestamp = self.timestamp.as_secs();
-        let revision = LocationEventTable::insert(&tx, timestamp, event.as_str())?;
-        RevisionTable::upsert(&tx, EventType::LOCATION, revision)
+    // Locations without persons do not exist, see update_or_delete()
+    fn existing(data: &LocationData) -> Option<Value> {
+        match data.total {
+            0 => None,
+            _ => Some(serde_json::to_value(data).unwrap()) // Errors should not happen, panic accepted
+        }
     }
 
-    fn stringify(event: LocationEvent) -> String {
-        serde_json::to_string(&event).unwrap() // Errors should not happen, panic accepted
+    fn write_event_and_revision(&mut self, tx: &Transaction, city: &str, before: Option<Value>, event: LocationEvent) -> Result<()> {
+        // Errors should not happen, panic accepted
+        let undo = invert_event(city, before, &serde_json::to_value(&event).unwrap());
+        let event = serde_json::to_string(&event).unwrap();
+        let timestamp = self.timestamp.as_secs();
+        let revision = LocationEventTable::insert(tx, timestamp, event.as_str(), Some(undo.to_string().as_str()))?;
+        RevisionTable::upsert(tx, EventType::LOCATION, revision)
     }
 }
 
@@ -143,18 +153,43 @@ impl AggregatorTrait for LocationAggregator {
     }
 
     fn get_all(&mut self, tx: &Transaction) -> Result<(usize, Self::Records)> {
-        let revision = RevisionTable::read(&tx, EventType::LOCATION)?;
-        let locations = LocationTable::select_all(&tx)?;
+        let revision = RevisionTable::read(tx, EventType::LOCATION)?;
+        let locations = LocationTable::select_all(tx)?;
         Ok((revision, locations))
     }
 
-    fn get_events(&mut self, tx: &Transaction, from_revision: usize) -> Result<Vec<String>> {
-        LocationEventTable::read(&tx, from_revision)
+    fn get_events(&mut self, tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, String)>> {
+        LocationEventTable::read(tx, from_revision, limit)
+    }
+
+    fn get_timed_events(&mut self, tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, u64, String)>> {
+        LocationEventTable::read_with_time(tx, from_revision, limit)
+    }
+
+    fn get_first_revision(&mut self, tx: &Transaction) -> Result<usize> {
+        // If all events were deleted, the next event will get the subsequent revision
+        match LocationEventTable::first_revision(tx)? {
+            Some(revision) => Ok(revision),
+            None => Ok(RevisionTable::read(tx, EventType::LOCATION)? + 1)
+        }
+    }
+
+    fn get_undo_events(&mut self, tx: &Transaction, revision: usize) -> Result<Vec<String>> {
+        LocationEventTable::read_undo(tx, revision)
+    }
+
+    fn get_first_revertible(&mut self, tx: &Transaction, revision: usize) -> Result<usize> {
+        LocationEventTable::first_revertible(tx, revision)
     }
 
     fn delete_events(&mut self, tx: &Transaction, created_before: Duration) -> Result<usize> {
-        let created_before = self.timestamp.as_secs() - created_before.as_secs();
-        LocationEventTable::delete_before(&tx, created_before)
+        let created_before = self.timestamp.as_secs().saturating_sub(created_before.as_secs());
+        LocationEventTable::delete_before(tx, created_before)
+    }
+
+    fn compact_events(&mut self, tx: &Transaction, created_before: Duration) -> Result<usize> {
+        let created_before = self.timestamp.as_secs().saturating_sub(created_before.as_secs());
+        LocationEventTable::compact_before(tx, created_before)
     }
 }
 
@@ -548,6 +583,10 @@ mod tests {
         get_events_and_compare(&tx, 0, &[
             r#"{"there":null}"#,
             r#"{"here":{"total":2,"married":1}}"#]);
+
+        let revision = aggregator.get_first_revision(&tx);
+        assert!(revision.is_ok());
+        assert_eq!(revision.unwrap(), 3);
         assert!(tx.commit().is_ok());
     }
 
@@ -572,12 +611,12 @@ mod tests {
 
     fn get_events_and_compare(tx: &Transaction, from_revision: usize, ref_events: &[&str]) {
         let mut aggregator = create_aggregator();
-        let events = aggregator.get_events(&tx, from_revision);
+        let events = aggregator.get_events(&tx, from_revision, 10);
         assert!(events.is_ok());
         let events = events.unwrap();
         assert_eq!(events.len(), ref_events.len());
         for (index, &ref_event) in ref_events.iter().enumerate() {
-            assert_eq!(events[index], *ref_event);
+            assert_eq!(events[index].1, *ref_event);
         }
     }
 
@@ -590,6 +629,6 @@ mod tests {
 
     fn check_events(tx: &Transaction, events_ref: &[&str]) {
         compare_revision(tx, EventType::LOCATION, events_ref.len());
-        compare_events(LocationEventTable::read(tx, 0), events_ref);
+        compare_events(LocationEventTable::read(tx, 0, 10), events_ref);
     }
 }
\ No newline at end of file
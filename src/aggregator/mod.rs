@@ -1,4 +1,6 @@
 mod aggregator_trait;
 mod person_aggregator;
 mod location_aggregator;
+mod person_validator;
+mod revision_notifier;
 pub mod aggregator_facade;
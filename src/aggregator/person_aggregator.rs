@@ -10,6 +10,7 @@ use crate::domain::person_event::PersonEvent;
 use crate::domain::person_id::PersonId;
 use crate::domain::person_map::PersonMap;
 use crate::domain::person_patch::PersonPatch;
+use crate::util::merge_patch::invert_event;
 use crate::util::timestamp::{BoxedTimestamp, UnixTimestamp};
 
 ///
@@ -33,14 +34,13 @@ impl PersonAggregator {
         Self{ timestamp }
     }
 
-    fn write_event_and_revision(&mut self, tx: &Transaction, timestamp: u64, event: PersonEvent) -> Result<()> {
-        let event = Self::stringify(event);
-        let revision = PersonEventTable::insert(&tx, timestamp, event.as_str())?;
-        RevisionTable::upsert(&tx, EventType::PERSON, revision)
-    }
-
-    fn stringify(event: PersonEvent) -> String {
-        serde_json::to_string(&event).unwrap() // Errors should not happen, panic accepted
+    fn write_event_and_revision(&mut self, tx: &Transaction, timestamp: u64, id: PersonId, before: Option<&PersonData>, event: PersonEvent) -> Result<()> {
+        // Errors should not happen, panic accepted
+        let before = before.map(|person| serde_json::to_value(person).unwrap());
+        let undo = invert_event(&id.to_string(), before, &serde_json::to_value(&event).unwrap());
+        let event = serde_json::to_string(&event).unwrap();
+        let revision = PersonEventTable::insert(tx, timestamp, event.as_str(), Some(undo.to_string().as_str()))?;
+        RevisionTable::upsert(tx, EventType::PERSON, revision)
     }
 }
 
@@ -48,45 +48,71 @@ impl AggregatorTrait for PersonAggregator {
     type Records = PersonMap;
 
     fn create_tables(&mut self, connection: &Connection) -> Result<()> {
-        PersonEventTable::create_table(&connection)
+        PersonEventTable::create_table(connection)
     }
 
     fn insert(&mut self, tx: &Transaction, id: PersonId, person: &PersonData) -> Result<()> {
         let timestamp = self.timestamp.as_secs();
         let event = PersonEvent::for_insert(id, person);
-        self.write_event_and_revision(&tx, timestamp, event)
+        self.write_event_and_revision(tx, timestamp, id, None, event)
     }
 
-    fn update(&mut self, tx: &Transaction, id: PersonId, _: &PersonData, patch: &PersonPatch) -> Result<()> {
+    fn update(&mut self, tx: &Transaction, id: PersonId, person: &PersonData, patch: &PersonPatch) -> Result<()> {
         let timestamp = self.timestamp.as_secs();
-        let event = PersonEvent::for_update(id, &patch);
-        self.write_event_and_revision(&tx, timestamp, event)
+        let event = PersonEvent::for_update(id, patch);
+        self.write_event_and_revision(tx, timestamp, id, Some(person), event)
     }
 
-    fn delete(&mut self, tx: &Transaction, id: PersonId, _: &PersonData) -> Result<()> {
+    fn delete(&mut self, tx: &Transaction, id: PersonId, person: &PersonData) -> Result<()> {
         let timestamp = self.timestamp.as_secs();
         let event = PersonEvent::for_delete(id);
-        self.write_event_and_revision(&tx, timestamp, event)
+        self.write_event_and_revision(tx, timestamp, id, Some(person), event)
     }
 
     fn get_all(&mut self, tx: &Transaction) -> Result<(usize, Self::Records)> {
-        let revision = RevisionTable::read(&tx, EventType::PERSON)?;
-        let persons = PersonTable::select_all(&tx)?;
+        let revision = RevisionTable::read(tx, EventType::PERSON)?;
+        let persons = PersonTable::select_all(tx)?;
         Ok((revision, persons))
     }
 
-    fn get_events(&mut self, tx: &Transaction, from_revision: usize) -> Result<Vec<String>> {
-        PersonEventTable::read(&tx, from_revision)
+    fn get_events(&mut self, tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, String)>> {
+        PersonEventTable::read(tx, from_revision, limit)
+    }
+
+    fn get_timed_events(&mut self, tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, u64, String)>> {
+        PersonEventTable::read_with_time(tx, from_revision, limit)
+    }
+
+    fn get_first_revision(&mut self, tx: &Transaction) -> Result<usize> {
+        // If all events were deleted, the next event will get the subsequent revision
+        match PersonEventTable::first_revision(tx)? {
+            Some(revision) => Ok(revision),
+            None => Ok(RevisionTable::read(tx, EventType::PERSON)? + 1)
+        }
+    }
+
+    fn get_undo_events(&mut self, tx: &Transaction, revision: usize) -> Result<Vec<String>> {
+        PersonEventTable::read_undo(tx, revision)
+    }
+
+    fn get_first_revertible(&mut self, tx: &Transaction, revision: usize) -> Result<usize> {
+        PersonEventTable::first_revertible(tx, revision)
     }
 
     fn delete_events(&mut self, tx: &Transaction, created_before: Duration) -> Result<usize> {
-        let created_before = self.timestamp.as_secs() - created_before.as_secs();
-        PersonEventTable::delete_before(&tx, created_before)
+        let created_before = self.timestamp.as_secs().saturating_sub(created_before.as_secs());
+        PersonEventTable::delete_before(tx, created_before)
+    }
+
+    fn compact_events(&mut self, tx: &Transaction, created_before: Duration) -> Result<usize> {
+        let created_before = self.timestamp.as_secs().saturating_sub(created_before.as_secs());
+        PersonEventTable::compact_before(tx, created_before)
     }
 }
 
 #[cfg(test)]
 pub mod tests {
+    use std::fmt::Debug;
     use std::time::Duration;
     use rusqlite::{Connection, Result, Transaction};
     use crate::aggregator::aggregator_trait::AggregatorTrait;
@@ -240,6 +266,44 @@ pub mod tests {
 
         get_events_and_compare(&tx, 0, &[
             r#"{"2":{"name":"Cam","city":"nowhere","spouse":123}}"#]);
+        get_first_revision_and_compare(&tx, 3);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_compact_events() {
+        let mut conn = create_connection();
+        let tx = conn.transaction().unwrap();
+
+        let person1 = PersonData::new("Ann", None, None);
+        let person2 = PersonData::new("Bob", None, None);
+        let patch2 = PersonPatch::new(Some("Cam"), Patch::Value("nowhere"), Patch::Absent);
+        let mut aggregator = create_aggregator();
+        assert!(aggregator.insert(&tx, PersonId::from(1), &person1).is_ok());
+        assert!(aggregator.insert(&tx, PersonId::from(2), &person2).is_ok());
+        assert!(aggregator.update(&tx, PersonId::from(2), &person2, &patch2).is_ok());
+        assert!(aggregator.delete(&tx, PersonId::from(1), &person1).is_ok());
+
+        // IncrementalTimestamp is at 5 inside compact_events() below; minus 1 yields 4,
+        // so it compacts all events <4 (i.e. the first three) and keeps the last one
+        let result = aggregator.compact_events(&tx, Duration::from_secs(1));
+        assert!(result.is_ok());
+        assert_eq!(result.unwrap(), 1); // Insert and update of person 2 were folded
+
+        get_events_and_compare(&tx, 0, &[
+            r#"{"1":{"name":"Ann"}}"#,
+            r#"{"2":{"city":"nowhere","name":"Cam"}}"#,
+            r#"{"1":null}"#]);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_get_first_revision_empty() {
+        let mut conn = create_connection();
+        let tx = conn.transaction().unwrap();
+        get_first_revision_and_compare(&tx, 1);
+        assert!(RevisionTable::upsert(&tx, EventType::PERSON, 5).is_ok()); // All events deleted
+        get_first_revision_and_compare(&tx, 6);
         assert!(tx.commit().is_ok());
     }
 
@@ -264,18 +328,25 @@ pub mod tests {
 
     fn get_events_and_compare(tx: &Transaction, from_revision: usize, ref_events: &[&str]) {
         let mut aggregator = create_aggregator();
-        let events = aggregator.get_events(&tx, from_revision);
+        let events = aggregator.get_events(&tx, from_revision, 10);
         assert!(events.is_ok());
         let events = events.unwrap();
         assert_eq!(events.len(), ref_events.len());
         for (index, &ref_event) in ref_events.iter().enumerate() {
-            assert_eq!(events[index], *ref_event);
+            assert_eq!(events[index].1, *ref_event);
         }
     }
 
+    fn get_first_revision_and_compare(tx: &Transaction, revision_ref: usize) {
+        let mut aggregator = create_aggregator();
+        let revision = aggregator.get_first_revision(tx);
+        assert!(revision.is_ok());
+        assert_eq!(revision.unwrap(), revision_ref);
+    }
+
     fn check_events(tx: &Transaction, events_ref: &[&str]) {
         compare_revision(tx, EventType::PERSON, events_ref.len());
-        compare_events(PersonEventTable::read(tx, 0), events_ref);
+        compare_events(PersonEventTable::read(tx, 0, 10), events_ref);
     }
 
     // Function is also used by LocationAggregator tests
@@ -286,14 +357,14 @@ pub mod tests {
     }
 
     // Function is also used by LocationAggregator tests
-    pub fn compare_events(events: Result<Vec<String>>, events_ref: &[&str]) {
+    pub fn compare_events<E: Debug>(events: Result<Vec<(usize, String)>, E>, events_ref: &[&str]) {
         assert!(events.is_ok());
         let events = events.unwrap();
         assert_eq!(events.len(), events_ref.len());
         for (index, &event_ref) in events_ref.iter().enumerate() {
             let event = events.get(index);
             assert!(event.is_some());
-            let event = event.unwrap();
+            let (_, event) = event.unwrap();
             assert_eq!(event, event_ref);
         }
     }
@@ -0,0 +1,133 @@
+use rusqlite::{Result, Transaction};
+use crate::database::person_table::PersonTable;
+use crate::domain::field_error::{BLANK, EMPTY, FieldError, NOT_FOUND, OUT_OF_RANGE, SELF_REFERENCE};
+use crate::domain::person_data::PersonData;
+use crate::domain::person_id::PersonId;
+use crate::domain::person_patch::PersonPatch;
+use crate::util::patch::Patch;
+
+///
+/// Checks person data and patches before
+/// [AggregatorFacade](crate::aggregator::aggregator_facade::AggregatorFacade) writes them.
+/// Spouses are looked up in the transaction of the write, so that a batch can refer
+/// to persons that were created by an earlier operation of the same batch.
+/// All functions return an empty list if there is nothing to complain about.
+///
+pub struct PersonValidator;
+
+impl PersonValidator {
+    /// Checks an id chosen by the client, before the database is asked about it.
+    pub fn validate_id(person_id: PersonId) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        if person_id > PersonId::MAX {
+            errors.push(FieldError::new(Some("id"), OUT_OF_RANGE, &format!("Id must not exceed {}", PersonId::MAX)));
+        }
+        errors
+    }
+
+    /// Checks a new or replacing person, ``person_id`` is ``None`` if the server assigns the id.
+    pub fn validate_data(tx: &Transaction, person_id: Option<PersonId>, person: &PersonData) -> Result<Vec<FieldError>> {
+        let mut errors = Vec::new();
+        Self::check_name(&person.name, &mut errors);
+        if let Some(spouse) = person.spouse {
+            Self::check_spouse(tx, person_id, spouse, &mut errors)?;
+        }
+        Ok(errors)
+    }
+
+    pub fn validate_patch(tx: &Transaction, person_id: PersonId, patch: &PersonPatch) -> Result<Vec<FieldError>> {
+        let mut errors = Vec::new();
+        if patch.name.is_none() && patch.city.is_absent() && patch.spouse.is_absent() {
+            errors.push(FieldError::new(None, EMPTY, "Patch must change at least one field"));
+        }
+        if let Some(name) = patch.name.as_ref() {
+            Self::check_name(name, &mut errors);
+        }
+        if let Patch::Value(spouse) = patch.spouse {
+            Self::check_spouse(tx, Some(person_id), spouse, &mut errors)?;
+        }
+        Ok(errors)
+    }
+
+    fn check_name(name: &str, errors: &mut Vec<FieldError>) {
+        if name.trim().is_empty() {
+            errors.push(FieldError::new(Some("name"), BLANK, "Name must not be blank"));
+        }
+    }
+
+    fn check_spouse(tx: &Transaction, person_id: Option<PersonId>, spouse: PersonId, errors: &mut Vec<FieldError>) -> Result<()> {
+        if person_id == Some(spouse) {
+            errors.push(FieldError::new(Some("spouse"), SELF_REFERENCE, "Person cannot be their own spouse"));
+        } else if PersonTable::select_revision(tx, spouse)?.is_none() {
+            errors.push(FieldError::new(Some("spouse"), NOT_FOUND, &format!("Spouse {} does not exist", spouse)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use crate::aggregator::person_validator::PersonValidator;
+    use crate::database::person_table::PersonTable;
+    use crate::domain::field_error::{BLANK, EMPTY, NOT_FOUND, OUT_OF_RANGE, SELF_REFERENCE};
+    use crate::domain::person_data::PersonData;
+    use crate::domain::person_id::PersonId;
+    use crate::domain::person_patch::PersonPatch;
+    use crate::util::patch::Patch;
+
+    #[test]
+    fn test_validate_id() {
+        assert!(PersonValidator::validate_id(PersonId::MAX).is_empty());
+        let errors = PersonValidator::validate_id(PersonId::from(u64::MAX));
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].field, Some("id"));
+        assert_eq!(errors[0].code, OUT_OF_RANGE);
+    }
+
+    #[test]
+    fn test_validate_data() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonTable::insert(&tx, &PersonData::new("Ann", None, None)).is_ok());
+
+        let person = PersonData::new("Bob", None, Some(PersonId::from(1)));
+        assert!(PersonValidator::validate_data(&tx, None, &person).unwrap().is_empty());
+        let person = PersonData::new(" ", None, Some(PersonId::from(2)));
+        let codes: Vec<_> = PersonValidator::validate_data(&tx, None, &person).unwrap().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![BLANK, NOT_FOUND]);
+        let codes: Vec<_> = PersonValidator::validate_data(&tx, Some(PersonId::from(2)), &person).unwrap().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![BLANK, SELF_REFERENCE]);
+        let person = PersonData::new("Bob", None, Some(PersonId::from(u64::MAX)));
+        let codes: Vec<_> = PersonValidator::validate_data(&tx, None, &person).unwrap().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![NOT_FOUND]);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_validate_patch() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonTable::insert(&tx, &PersonData::new("Ann", None, None)).is_ok());
+        assert!(PersonTable::insert(&tx, &PersonData::new("Bob", None, None)).is_ok());
+
+        let patch = PersonPatch::new(None, Patch::Null, Patch::Value(PersonId::from(2)));
+        assert!(PersonValidator::validate_patch(&tx, PersonId::from(1), &patch).unwrap().is_empty());
+        let patch = PersonPatch::new(None, Patch::Absent, Patch::Absent);
+        let errors = PersonValidator::validate_patch(&tx, PersonId::from(1), &patch).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, EMPTY);
+        let patch = PersonPatch::new(Some(""), Patch::Absent, Patch::Value(PersonId::from(1)));
+        let codes: Vec<_> = PersonValidator::validate_patch(&tx, PersonId::from(1), &patch).unwrap().iter().map(|e| e.code).collect();
+        assert_eq!(codes, vec![BLANK, SELF_REFERENCE]);
+        assert!(tx.commit().is_ok());
+    }
+
+    fn create_connection_and_table() -> Connection {
+        let conn = Connection::open(":memory:");
+        assert!(conn.is_ok());
+        let conn = conn.unwrap();
+        assert!(PersonTable::create_table(&conn).is_ok());
+        conn
+    }
+}
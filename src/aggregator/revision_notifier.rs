@@ -0,0 +1,100 @@
+use tokio::sync::watch;
+use crate::domain::event_type::EventType;
+
+///
+/// Publishes the latest committed revision per [EventType](crate::domain::event_type::EventType)
+/// through tokio ``watch`` channels. Event streams subscribe to the channels to be woken up
+/// immediately after a commit, rather than waiting for the next poll.
+/// A third channel publishes the latest commit id for the combined stream of both event types.
+///
+pub struct RevisionNotifier {
+    person: watch::Sender<usize>,
+    location: watch::Sender<usize>,
+    commits: watch::Sender<usize>
+}
+
+impl RevisionNotifier {
+    pub fn new() -> Self {
+        let (person, _) = watch::channel(0);
+        let (location, _) = watch::channel(0);
+        let (commits, _) = watch::channel(0);
+        Self { person, location, commits }
+    }
+
+    pub fn subscribe(&self, event_type: EventType) -> watch::Receiver<usize> {
+        self.sender(event_type).subscribe()
+    }
+
+    pub fn subscribe_commits(&self) -> watch::Receiver<usize> {
+        self.commits.subscribe()
+    }
+
+    /// Notifies the subscribers of ``event_type``, but only if ``revision`` increased.
+    pub fn notify(&self, event_type: EventType, revision: usize) {
+        Self::send_if_increased(self.sender(event_type), revision);
+    }
+
+    /// Notifies the subscribers of the commits, but only if ``commit_id`` increased.
+    pub fn notify_commit(&self, commit_id: usize) {
+        Self::send_if_increased(&self.commits, commit_id);
+    }
+
+    fn send_if_increased(sender: &watch::Sender<usize>, value: usize) {
+        sender.send_if_modified(|current| {
+            if value > *current {
+                *current = value;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    fn sender(&self, event_type: EventType) -> &watch::Sender<usize> {
+        match event_type {
+            EventType::PERSON => &self.person,
+            EventType::LOCATION => &self.location
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aggregator::revision_notifier::RevisionNotifier;
+    use crate::domain::event_type::EventType;
+
+    #[test]
+    fn test_notify() {
+        let notifier = RevisionNotifier::new();
+        let mut person_rx = notifier.subscribe(EventType::PERSON);
+        let location_rx = notifier.subscribe(EventType::LOCATION);
+        notifier.notify(EventType::PERSON, 3);
+        assert!(person_rx.has_changed().unwrap());
+        assert_eq!(*person_rx.borrow_and_update(), 3);
+        assert!(!location_rx.has_changed().unwrap());
+    }
+
+    #[test]
+    fn test_notify_unchanged() {
+        let notifier = RevisionNotifier::new();
+        notifier.notify(EventType::LOCATION, 3);
+        let location_rx = notifier.subscribe(EventType::LOCATION);
+        notifier.notify(EventType::LOCATION, 3);
+        notifier.notify(EventType::LOCATION, 2);
+        assert!(!location_rx.has_changed().unwrap());
+        assert_eq!(*location_rx.borrow(), 3);
+    }
+
+    #[test]
+    fn test_notify_commit() {
+        let notifier = RevisionNotifier::new();
+        let mut commit_rx = notifier.subscribe_commits();
+        let person_rx = notifier.subscribe(EventType::PERSON);
+        notifier.notify_commit(1);
+        assert!(commit_rx.has_changed().unwrap());
+        assert_eq!(*commit_rx.borrow_and_update(), 1);
+        assert!(!person_rx.has_changed().unwrap());
+        notifier.notify_commit(1);
+        assert!(!commit_rx.has_changed().unwrap());
+    }
+}
@@ -1,10 +1,13 @@
 use log::{debug, info};
+use std::env;
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::{join, signal};
 use tokio::sync::broadcast;
-use aggregate_event_duality::aggregator::aggregator_facade::AggregatorFacade;
+use aggregate_event_duality::aggregator::aggregator_facade::{AggregatorFacade, EventRetention};
+use aggregate_event_duality::error::DomainError;
+use aggregate_event_duality::rest::event_stream_config::EventStreamConfig;
 use aggregate_event_duality::rest::http_server::spawn_http_server;
 use aggregate_event_duality::util::deletion_scheduler::{MutexDeletionTask, spawn_deletion_scheduler};
 
@@ -12,7 +15,12 @@ use aggregate_event_duality::util::deletion_scheduler::{MutexDeletionTask, spawn
 async fn main() -> Result<(), Box<dyn Error>> {
     env_logger::init();
 
-    let aggregator = AggregatorFacade::new(":memory:")?;
+    // Outdated events are deleted, unless environment variable EVENT_RETENTION is set to "compact"
+    let retention = match env::var("EVENT_RETENTION") {
+        Ok(value) => value.parse::<EventRetention>()?,
+        Err(_) => EventRetention::Delete
+    };
+    let aggregator = AggregatorFacade::with_retention(":memory:", retention)?;
     let aggregator= Arc::new(Mutex::new(aggregator));
 
     // Channel to inform the HTTP server and the delete scheduler to terminate.
@@ -23,10 +31,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Start a task that periodically deletes older events.
     // Note that AggregatorFacade implements trait DeletionTask.
     let period = Duration::from_secs(120);
-    let deletion_task: MutexDeletionTask<rusqlite::Error> = aggregator.clone();
+    let deletion_task: MutexDeletionTask<DomainError> = aggregator.clone();
     let delete_scheduler = spawn_deletion_scheduler(&deletion_task, rx1, period);
 
-    let http_server = spawn_http_server(&aggregator, rx2, 5);
+    // Event streams are woken up after every commit, so polling every 60 seconds is just a fallback.
+    // Idle streams get a keep-alive comment every 15 seconds, and clients should reconnect after 3 seconds,
+    // unless environment variables EVENT_STREAM_KEEP_ALIVE and EVENT_STREAM_RETRY say otherwise.
+    let config = EventStreamConfig::from_env()?;
+    let http_server = spawn_http_server(&aggregator, rx2, config);
 
     signal::ctrl_c().await?;
     debug!("Termination signal received");
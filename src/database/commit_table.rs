@@ -0,0 +1,181 @@
+use log::debug;
+use rusqlite::{Connection, params, Result, Transaction};
+use crate::database::event_table::{LocationEventTable, PersonEventTable};
+use crate::domain::event_type::EventType;
+
+// A single row holds the id of the latest commit that wrote events
+const CREATE_COMMIT_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS commit_counter (
+        id INTEGER NOT NULL PRIMARY KEY,
+        commit_id INTEGER NOT NULL
+    )";
+
+const UPSERT_COMMIT: &str =
+    "INSERT INTO commit_counter (id, commit_id) VALUES (0, ?)
+      ON CONFLICT(id) DO
+      UPDATE SET commit_id = excluded.commit_id";
+
+const SELECT_COMMIT: &str =
+    "SELECT commit_id FROM commit_counter WHERE id = 0";
+
+///
+/// Numbers the transactions that wrote events. Every event in
+/// [PersonEventTable](crate::database::event_table::PersonEventTable) and
+/// [LocationEventTable](crate::database::event_table::LocationEventTable) is annotated with
+/// the id of its transaction, so that the events of both tables can be read in commit order.
+///
+pub struct CommitTable;
+
+impl CommitTable {
+    pub fn create_table(conn: &Connection) -> Result<()> {
+        debug!("Execute\n{}", CREATE_COMMIT_TABLE);
+        conn.execute(CREATE_COMMIT_TABLE, [])?;
+        Ok(())
+    }
+
+    pub fn upsert(tx: &Transaction, commit_id: usize) -> Result<()> {
+        debug!("Execute\n{} with: {}", UPSERT_COMMIT, commit_id);
+        tx.execute(UPSERT_COMMIT, params![commit_id])?;
+        Ok(())
+    }
+
+    /// Returns the id of the latest commit that wrote events, or 0 if there was none.
+    pub fn read(tx: &Transaction) -> Result<usize> {
+        let mut stmt = tx.prepare(SELECT_COMMIT)?;
+        let mut rows = stmt.query([])?;
+        match rows.next()? {
+            Some(row) => Ok(row.get(0)?),
+            None => Ok(0)
+        }
+    }
+
+    ///
+    /// Returns the events of at most ``limit`` commits, starting at ``from_commit``, as tuples of
+    /// commit id, event type, revision, and stringified event. The events are ordered by commit,
+    /// within a commit person events come first.
+    ///
+    pub fn read_events(tx: &Transaction, from_commit: usize, limit: usize) -> Result<Vec<(usize, EventType, usize, String)>> {
+        let stmt = format!(
+            "WITH commits (commit_id) AS (
+                SELECT commit_id FROM {1} WHERE commit_id >= ?1
+                UNION
+                SELECT commit_id FROM {3} WHERE commit_id >= ?1
+                ORDER BY commit_id LIMIT ?2
+            ), events (commit_id, type, revision, event) AS (
+                SELECT commit_id, {0}, revision, event FROM {1}
+                UNION ALL
+                SELECT commit_id, {2}, revision, event FROM {3}
+            )
+            SELECT commit_id, type, revision, event FROM events
+            WHERE commit_id IN commits
+            ORDER BY commit_id, type, revision",
+            EventType::PERSON as u16, PersonEventTable::name(),
+            EventType::LOCATION as u16, LocationEventTable::name());
+        debug!("Execute\n{} with: {} and {}", stmt, from_commit, limit);
+        let mut stmt = tx.prepare(stmt.as_str())?;
+        let rows = stmt.query_map([from_commit, limit], |row| {
+            let commit_id: usize = row.get(0)?;
+            let event_type = match row.get::<_, u16>(1)? {
+                1 => EventType::PERSON,
+                _ => EventType::LOCATION
+            };
+            let revision: usize = row.get(2)?;
+            let json: String = row.get(3)?;
+            Ok((commit_id, event_type, revision, json))
+        })?;
+        let mut events = Vec::new();
+        for row in rows {
+            events.push(row?);
+        }
+        Ok(events)
+    }
+
+    /// Returns the lowest commit id of all events still stored, or ``None`` if there are no events.
+    pub fn first_commit(tx: &Transaction) -> Result<Option<usize>> {
+        let stmt = format!(
+            "SELECT MIN(commit_id) FROM (
+                SELECT commit_id FROM {} UNION ALL SELECT commit_id FROM {}
+            )",
+            PersonEventTable::name(), LocationEventTable::name());
+        debug!("Execute\n{}", stmt);
+        tx.query_row(stmt.as_str(), [], |row| row.get(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use crate::database::commit_table::CommitTable;
+    use crate::database::event_table::{LocationEventTable, PersonEventTable};
+    use crate::domain::event_type::EventType;
+
+    #[test]
+    fn test_upsert() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        assert_eq!(CommitTable::read(&tx).unwrap(), 0);
+        assert!(CommitTable::upsert(&tx, 1).is_ok());
+        assert!(CommitTable::upsert(&tx, 2).is_ok());
+        assert_eq!(CommitTable::read(&tx).unwrap(), 2);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_read_events() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        insert_commit(&tx, 1, &["p1"], &["l1", "l2"]);
+        insert_commit(&tx, 2, &["p2"], &[]);
+        insert_commit(&tx, 3, &["p3"], &["l3"]);
+
+        let events = CommitTable::read_events(&tx, 1, 2).unwrap();
+        assert_eq!(events, vec![
+            (1, EventType::PERSON, 1, String::from("p1")),
+            (1, EventType::LOCATION, 1, String::from("l1")),
+            (1, EventType::LOCATION, 2, String::from("l2")),
+            (2, EventType::PERSON, 2, String::from("p2"))
+        ]);
+        let events = CommitTable::read_events(&tx, 3, 2).unwrap();
+        assert_eq!(events, vec![
+            (3, EventType::PERSON, 3, String::from("p3")),
+            (3, EventType::LOCATION, 3, String::from("l3"))
+        ]);
+        assert!(CommitTable::read_events(&tx, 4, 2).unwrap().is_empty());
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_first_commit() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        assert_eq!(CommitTable::first_commit(&tx).unwrap(), None);
+        insert_commit(&tx, 1, &["p1"], &["l1"]);
+        insert_commit(&tx, 2, &["p2"], &[]);
+        assert!(PersonEventTable::delete_before(&tx, 2).is_ok()); // Timestamp is the commit id
+        assert_eq!(CommitTable::first_commit(&tx).unwrap(), Some(1)); // Location event still there
+        assert!(LocationEventTable::delete_before(&tx, 2).is_ok());
+        assert_eq!(CommitTable::first_commit(&tx).unwrap(), Some(2));
+        assert!(tx.commit().is_ok());
+    }
+
+    fn insert_commit(tx: &rusqlite::Transaction, commit_id: usize, person_events: &[&str], location_events: &[&str]) {
+        for event in person_events {
+            assert!(PersonEventTable::insert(tx, commit_id as u64, event, None).is_ok());
+        }
+        for event in location_events {
+            assert!(LocationEventTable::insert(tx, commit_id as u64, event, None).is_ok());
+        }
+        assert!(PersonEventTable::assign_commit(tx, commit_id).is_ok());
+        assert!(LocationEventTable::assign_commit(tx, commit_id).is_ok());
+    }
+
+    fn create_connection_and_tables() -> Connection {
+        let conn = Connection::open(":memory:");
+        assert!(conn.is_ok());
+        let conn = conn.unwrap();
+        assert!(CommitTable::create_table(&conn).is_ok());
+        assert!(PersonEventTable::create_table(&conn).is_ok());
+        assert!(LocationEventTable::create_table(&conn).is_ok());
+        conn
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::{BTreeMap, HashMap};
 use log::debug;
 use rusqlite::{Connection, params, Result, Transaction};
+use serde_json::{Map, Value};
+use crate::util::merge_patch::compose;
 
 pub type PersonEventTable = EventTable<0>;
 pub type LocationEventTable = EventTable<1>;
@@ -15,41 +18,125 @@ impl<const TABLE_TYPE: usize> EventTable<TABLE_TYPE> {
     pub fn create_table(conn: &Connection) -> Result<()> {
         let stmt = format!(
             "CREATE TABLE IF NOT EXISTS {} (
-                revision INTEGER NOT NULL PRIMARY KEY,
+                revision INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
                 time INTEGER NOT NULL,
-                event TEXT NOT NULL
+                event TEXT NOT NULL,
+                undo TEXT,
+                commit_id INTEGER
             )", Self::table_name(TABLE_TYPE));
         debug!("Execute\n{}", stmt);
         conn.execute(stmt.as_str(), [])?;
+        // Commits assign their id to the new events and are read by id, see CommitTable
+        let stmt = format!(
+            "CREATE INDEX IF NOT EXISTS {0}_commit ON {0} (commit_id)",
+            Self::table_name(TABLE_TYPE));
+        debug!("Execute\n{}", stmt);
+        conn.execute(stmt.as_str(), [])?;
+        // Only compacted events lack undo, so this index stays small, see first_revertible
+        let stmt = format!(
+            "CREATE INDEX IF NOT EXISTS {0}_irreversible ON {0} (revision) WHERE undo IS NULL",
+            Self::table_name(TABLE_TYPE));
+        debug!("Execute\n{}", stmt);
+        conn.execute(stmt.as_str(), [])?;
         Ok(())
     }
 
-    pub fn insert(tx: &Transaction, timestamp: u64, event: &str) -> Result<usize> {
+    ///
+    /// Inserts ``event`` together with the inverse merge patch ``undo``, which restores the aggregates
+    /// of the previous revision. Events without ``undo`` cannot be reverted. The inverse patch holds the
+    /// previous values of all changed fields, so it roughly doubles the storage of an event
+    /// until the event is deleted or compacted.
+    ///
+    pub fn insert(tx: &Transaction, timestamp: u64, event: &str, undo: Option<&str>) -> Result<usize> {
         let stmt = format!(
-            "INSERT INTO {} (time, event) VALUES (?,?)",
+            "INSERT INTO {} (time, event, undo) VALUES (?,?,?)",
             Self::table_name(TABLE_TYPE));
-        debug!("Execute\n{}\nwith: {} and {}", stmt, timestamp, event);
-        tx.execute(stmt.as_str(), params![timestamp, event])?;
+        debug!("Execute\n{}\nwith: {}, {}, and {:?}", stmt, timestamp, event, undo);
+        tx.execute(stmt.as_str(), params![timestamp, event, undo])?;
         Ok(tx.last_insert_rowid() as usize)
     }
 
-    pub fn read(tx: &Transaction, from_revision: usize) -> Result<Vec<String>> {
+    /// Returns at most ``limit`` events with a revision greater or equal ``from_revision``
+    /// as tuples of revision and stringified event, ordered by revision.
+    pub fn read(tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, String)>> {
+        let events = Self::read_with_time(tx, from_revision, limit)?;
+        Ok(events.into_iter().map(|(revision, _, json)| (revision, json)).collect())
+    }
+
+    /// Like [read](Self::read), but returns tuples of revision, creation timestamp, and stringified event.
+    pub fn read_with_time(tx: &Transaction, from_revision: usize, limit: usize) -> Result<Vec<(usize, u64, String)>> {
         let stmt = format!(
-            "SELECT event FROM {} WHERE revision >= ? ORDER BY revision",
+            "SELECT revision, time, event FROM {} WHERE revision >= ? ORDER BY revision LIMIT ?",
             Self::table_name(TABLE_TYPE));
-        debug!("Execute\n{} with: {}", stmt, from_revision);
+        debug!("Execute\n{} with: {} and {}", stmt, from_revision, limit);
         let mut stmt = tx.prepare(stmt.as_str())?;
-        let rows = stmt.query_map([from_revision], |row| {
-            let json: String = row.get(0)?;
-            Ok(json)
+        let rows = stmt.query_map([from_revision, limit], |row| {
+            let revision: usize = row.get(0)?;
+            let time: u64 = row.get(1)?;
+            let json: String = row.get(2)?;
+            Ok((revision, time, json))
         })?;
-        let mut events : Vec<String> = Vec::new();
+        let mut events : Vec<(usize, u64, String)> = Vec::new();
         for row in rows {
             events.push(row?);
         }
         Ok(events)
     }
 
+    /// Returns the inverse patches of all events with a revision greater than ``revision``, latest first.
+    pub fn read_undo(tx: &Transaction, revision: usize) -> Result<Vec<String>> {
+        let stmt = format!(
+            "SELECT undo FROM {} WHERE revision > ? ORDER BY revision DESC",
+            Self::table_name(TABLE_TYPE));
+        debug!("Execute\n{} with: {}", stmt, revision);
+        let mut stmt = tx.prepare(stmt.as_str())?;
+        let rows = stmt.query_map([revision], |row| row.get(0))?;
+        let mut undos = Vec::new();
+        for row in rows {
+            undos.push(row?);
+        }
+        Ok(undos)
+    }
+
+    ///
+    /// Returns the oldest revision that can be restored from the aggregates of ``revision``, i.e. the revision
+    /// before the oldest event of the unbroken sequence of revertible events that ends at ``revision``.
+    /// The sequence breaks at deleted events and at compacted events, which cannot be reverted.
+    /// Deletion removes the oldest events, and compaction leaves only irreversible events behind,
+    /// so the sequence starts after the latest irreversible event or the oldest event still stored.
+    ///
+    pub fn first_revertible(tx: &Transaction, revision: usize) -> Result<usize> {
+        let stmt = format!(
+            "SELECT CASE WHEN EXISTS (SELECT 1 FROM {0} WHERE revision = ?1 AND undo IS NOT NULL)
+                THEN MAX(IFNULL((SELECT MAX(revision) FROM {0} WHERE revision <= ?1 AND undo IS NULL), 0),
+                    (SELECT MIN(revision) FROM {0}) - 1)
+                ELSE ?1 END",
+            Self::table_name(TABLE_TYPE));
+        debug!("Execute\n{} with: {}", stmt, revision);
+        tx.query_row(stmt.as_str(), [revision], |row| row.get(0))
+    }
+
+    /// Returns the lowest revision still stored in the table, or ``None`` if the table is empty.
+    pub fn first_revision(tx: &Transaction) -> Result<Option<usize>> {
+        let stmt = format!(
+            "SELECT MIN(revision) FROM {}",
+            Self::table_name(TABLE_TYPE));
+        debug!("Execute\n{}", stmt);
+        tx.query_row(stmt.as_str(), [], |row| row.get(0))
+    }
+
+    /// Assigns ``commit_id`` to all events inserted in the current transaction, i.e. the events
+    /// without commit id, see [CommitTable](crate::database::commit_table::CommitTable).
+    /// Returns the number of events updated.
+    pub fn assign_commit(tx: &Transaction, commit_id: usize) -> Result<usize> {
+        let stmt = format!(
+            "UPDATE {} SET commit_id = ? WHERE commit_id IS NULL",
+            Self::table_name(TABLE_TYPE));
+        debug!("Execute\n{}\nwith: {}", stmt, commit_id);
+        let row_count = tx.execute(stmt.as_str(), [commit_id])?;
+        Ok(row_count)
+    }
+
     pub fn delete_before(tx: &Transaction, timestamp: u64) -> Result<usize> {
         let stmt = format!(
             "DELETE FROM {} WHERE time < ?",
@@ -59,6 +146,77 @@ impl<const TABLE_TYPE: usize> EventTable<TABLE_TYPE> {
         Ok(row_count)
     }
 
+    pub fn name() -> &'static str {
+        Self::table_name(TABLE_TYPE)
+    }
+
+    ///
+    /// Folds all events created before ``timestamp`` into one merge patch per top-level key
+    /// (i.e. person id or city). The folded patch replaces the latest of these events for the key,
+    /// so it keeps a revision that consumers may already have seen. If the last change deleted
+    /// the aggregate, the folded patch is a tombstone ``{<key>:null}``. An aggregate that was re-created
+    /// after a deletion keeps the tombstone as a separate event before the folded re-creation,
+    /// because merge patches cannot express that the re-created aggregate replaces the old one.
+    /// All other events created before ``timestamp`` are deleted. The folded patches cannot be reverted, so their inverse patches
+    /// are dropped. Returns the number of deleted events.
+    ///
+    pub fn compact_before(tx: &Transaction, timestamp: u64) -> Result<usize> {
+        let stmt = format!(
+            "SELECT revision, event FROM {} WHERE time < ? ORDER BY revision",
+            Self::table_name(TABLE_TYPE));
+        debug!("Execute\n{}\nwith: {}", stmt, timestamp);
+        let mut stmt = tx.prepare(stmt.as_str())?;
+        let rows = stmt.query_map([timestamp], |row| {
+            let revision: usize = row.get(0)?;
+            let json: String = row.get(1)?;
+            Ok((revision, json))
+        })?;
+        let mut revisions: Vec<usize> = Vec::new();
+        // Per key, the folded patches since the latest tombstone, each with the revision that keeps it
+        let mut folded: HashMap<String, Vec<(usize, Value)>> = HashMap::new();
+        for row in rows {
+            let (revision, json) = row?;
+            // Events were serialized by the aggregators, errors should not happen, panic accepted
+            let event: Map<String, Value> = serde_json::from_str(json.as_str()).unwrap();
+            for (key, patch) in event {
+                let segments = folded.entry(key).or_default();
+                if patch.is_null() {
+                    segments.clear(); // The tombstone supersedes all earlier patches
+                }
+                match segments.last_mut() {
+                    Some((_, value)) if value.is_null() && patch.is_object() => segments.push((revision, patch)),
+                    Some((latest, value)) => {
+                        *latest = revision;
+                        compose(value, patch);
+                    },
+                    None => segments.push((revision, patch))
+                }
+            }
+            revisions.push(revision);
+        }
+        // Events that hold the folded patch of at least one key
+        let mut kept: BTreeMap<usize, Map<String, Value>> = BTreeMap::new();
+        for (key, segments) in folded {
+            for (revision, value) in segments {
+                kept.entry(revision).or_default().insert(key.clone(), value);
+            }
+        }
+        let update = format!("UPDATE {} SET event = ?, undo = NULL WHERE revision = ?", Self::table_name(TABLE_TYPE));
+        let delete = format!("DELETE FROM {} WHERE revision = ?", Self::table_name(TABLE_TYPE));
+        let mut row_count = 0;
+        for revision in revisions {
+            match kept.get(&revision) {
+                Some(event) => {
+                    let event = serde_json::to_string(event).unwrap();
+                    tx.execute(update.as_str(), params![event, revision])?;
+                },
+                None => row_count += tx.execute(delete.as_str(), [revision])?
+            }
+        }
+        debug!("Compacted {} events", row_count);
+        Ok(row_count)
+    }
+
     // Necessary translation function between usize and str constants.
     // Can be removed once Rust stably supports const str generics.
     // https://rust-lang.github.io/rfcs/2000-const-generics.html
@@ -80,7 +238,7 @@ mod tests {
     fn test_insert() {
         let mut conn = create_connection_and_table();
         let tx = conn.transaction().unwrap();
-        let revision = PersonEventTable::insert(&tx, 0, "foo");
+        let revision = PersonEventTable::insert(&tx, 0, "foo", None);
         assert!(tx.commit().is_ok());
         assert!(revision.is_ok());
         assert_eq!(revision.unwrap(), 1);
@@ -90,7 +248,7 @@ mod tests {
     fn test_read_from_empty() {
         let mut conn = create_connection_and_table();
         let tx = conn.transaction().unwrap();
-        let events = PersonEventTable::read(&tx, 1);
+        let events = PersonEventTable::read(&tx, 1, 10);
         assert!(tx.commit().is_ok());
         assert!(events.is_ok());
         assert_eq!(events.unwrap().len(), 0);
@@ -100,25 +258,130 @@ mod tests {
     fn test_read_from() {
         let mut conn = create_connection_and_table();
         let tx = conn.transaction().unwrap();
-        assert!(PersonEventTable::insert(&tx, 1, "foo").is_ok());
-        assert!(PersonEventTable::insert(&tx, 2, "bar").is_ok());
+        assert!(PersonEventTable::insert(&tx, 1, "foo", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, "bar", None).is_ok());
         assert!(tx.commit().is_ok());
 
         let tx = conn.transaction().unwrap();
-        let events = PersonEventTable::read(&tx, 2);
+        let events = PersonEventTable::read(&tx, 2, 10);
         assert!(tx.commit().is_ok());
         assert!(events.is_ok());
         let events = events.unwrap();
         assert_eq!(events.len(), 1);
-        assert_eq!(events[0], "bar");
+        assert_eq!(events[0], (2, String::from("bar")));
+    }
+
+    #[test]
+    fn test_read_limit() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, "foo", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, "bar", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 3, "baz", None).is_ok());
+        assert!(tx.commit().is_ok());
+
+        let tx = conn.transaction().unwrap();
+        let events = PersonEventTable::read(&tx, 1, 2);
+        assert!(tx.commit().is_ok());
+        assert!(events.is_ok());
+        let events = events.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], (1, String::from("foo")));
+        assert_eq!(events[1], (2, String::from("bar")));
+    }
+
+    #[test]
+    fn test_read_with_time() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 10, "foo", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 20, "bar", None).is_ok());
+        assert!(tx.commit().is_ok());
+
+        let tx = conn.transaction().unwrap();
+        let events = PersonEventTable::read_with_time(&tx, 2, 10);
+        assert!(tx.commit().is_ok());
+        assert!(events.is_ok());
+        assert_eq!(events.unwrap(), vec![(2, 20, String::from("bar"))]);
+    }
+
+    #[test]
+    fn test_assign_commit() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, "foo", None).is_ok());
+        assert_eq!(PersonEventTable::assign_commit(&tx, 1).unwrap(), 1);
+        assert!(PersonEventTable::insert(&tx, 2, "bar", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 3, "baz", None).is_ok());
+        assert_eq!(PersonEventTable::assign_commit(&tx, 2).unwrap(), 2);
+        assert_eq!(PersonEventTable::assign_commit(&tx, 3).unwrap(), 0);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_compact_before() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, r#"{"1":{"name":"Ann","city":"Rome"}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, r#"{"2":{"name":"Bob"}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 3, r#"{"1":{"city":null}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 4, r#"{"2":null}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 5, r#"{"1":{"name":"Cam"}}"#, None).is_ok());
+        assert!(tx.commit().is_ok());
+
+        let tx = conn.transaction().unwrap();
+        let row_count = PersonEventTable::compact_before(&tx, 5);
+        assert!(row_count.is_ok());
+        assert_eq!(row_count.unwrap(), 2);
+        let events = PersonEventTable::read(&tx, 1, 10);
+        assert!(tx.commit().is_ok());
+        assert_eq!(events.unwrap(), vec![
+            (3, String::from(r#"{"1":{"city":null,"name":"Ann"}}"#)),
+            (4, String::from(r#"{"2":null}"#)),
+            (5, String::from(r#"{"1":{"name":"Cam"}}"#)) // Not compacted
+        ]);
+    }
+
+    #[test]
+    fn test_compact_before_recreated() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, r#"{"5":{"name":"Ann","city":"Rome"}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, r#"{"5":null}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 3, r#"{"5":{"name":"Bob"}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 4, r#"{"5":{"city":"Oslo"}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 5, r#"{"5":null}"#, None).is_ok());
+        assert_eq!(PersonEventTable::compact_before(&tx, 6).unwrap(), 4);
+        assert_eq!(PersonEventTable::read(&tx, 1, 10).unwrap(), vec![(5, String::from(r#"{"5":null}"#))]);
+
+        assert!(PersonEventTable::insert(&tx, 6, r#"{"5":{"name":"Cam"}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 7, r#"{"5":{"city":"Rome"}}"#, None).is_ok());
+        assert_eq!(PersonEventTable::compact_before(&tx, 8).unwrap(), 1);
+        assert_eq!(PersonEventTable::read(&tx, 1, 10).unwrap(), vec![
+            (5, String::from(r#"{"5":null}"#)),
+            (7, String::from(r#"{"5":{"city":"Rome","name":"Cam"}}"#))
+        ]);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_compact_before_twice() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, r#"{"1":{"name":"Ann"}}"#, None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, r#"{"1":{"name":"Bob"}}"#, None).is_ok());
+        assert_eq!(PersonEventTable::compact_before(&tx, 3).unwrap(), 1);
+        assert_eq!(PersonEventTable::compact_before(&tx, 3).unwrap(), 0);
+        assert_eq!(PersonEventTable::read(&tx, 1, 10).unwrap(), vec![(2, String::from(r#"{"1":{"name":"Bob"}}"#))]);
+        assert!(tx.commit().is_ok());
     }
 
     #[test]
     fn test_delete_before() {
         let mut conn = create_connection_and_table();
         let tx = conn.transaction().unwrap();
-        assert!(PersonEventTable::insert(&tx, 1, "foo").is_ok());
-        assert!(PersonEventTable::insert(&tx, 2, "bar").is_ok());
+        assert!(PersonEventTable::insert(&tx, 1, "foo", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, "bar", None).is_ok());
         assert!(tx.commit().is_ok());
 
         let tx = conn.transaction().unwrap();
@@ -128,12 +391,89 @@ mod tests {
         assert_eq!(count.unwrap(), 1);
 
         let tx = conn.transaction().unwrap();
-        let events = PersonEventTable::read(&tx, 0); // Read all
+        let events = PersonEventTable::read(&tx, 0, 10); // Read all
         assert!(tx.commit().is_ok());
         assert!(events.is_ok());
         let events = events.unwrap();
         assert_eq!(events.len(), 1);
-        assert_eq!(events[0], "bar");
+        assert_eq!(events[0], (2, String::from("bar")));
+    }
+
+    #[test]
+    fn test_first_revision() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, "foo", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, "bar", None).is_ok());
+        assert!(PersonEventTable::delete_before(&tx, 2).is_ok());
+        let revision = PersonEventTable::first_revision(&tx);
+        assert!(tx.commit().is_ok());
+        assert!(revision.is_ok());
+        assert_eq!(revision.unwrap(), Some(2));
+    }
+
+    #[test]
+    fn test_first_revision_empty() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        let revision = PersonEventTable::first_revision(&tx);
+        assert!(tx.commit().is_ok());
+        assert!(revision.is_ok());
+        assert_eq!(revision.unwrap(), None);
+    }
+
+    #[test]
+    fn test_insert_after_delete_all() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, "foo", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, "bar", None).is_ok());
+        assert!(PersonEventTable::delete_before(&tx, 3).is_ok());
+        let revision = PersonEventTable::insert(&tx, 3, "baz", None);
+        assert!(tx.commit().is_ok());
+        assert!(revision.is_ok());
+        assert_eq!(revision.unwrap(), 3); // Revisions are never reused
+    }
+
+    #[test]
+    fn test_read_undo() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, "foo", Some("oof")).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, "bar", Some("rab")).is_ok());
+        assert!(PersonEventTable::insert(&tx, 3, "baz", Some("zab")).is_ok());
+        assert_eq!(PersonEventTable::read_undo(&tx, 1).unwrap(), vec![String::from("zab"), String::from("rab")]);
+        assert!(PersonEventTable::read_undo(&tx, 3).unwrap().is_empty());
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_first_revertible() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert_eq!(PersonEventTable::first_revertible(&tx, 0).unwrap(), 0);
+        assert!(PersonEventTable::insert(&tx, 1, "foo", Some("oof")).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, "bar", None).is_ok());
+        assert!(PersonEventTable::insert(&tx, 3, "baz", Some("zab")).is_ok());
+        assert!(PersonEventTable::insert(&tx, 4, "qux", Some("xuq")).is_ok());
+        assert_eq!(PersonEventTable::first_revertible(&tx, 4).unwrap(), 2);
+        assert_eq!(PersonEventTable::first_revertible(&tx, 1).unwrap(), 0);
+        assert!(PersonEventTable::delete_before(&tx, 4).is_ok());
+        assert_eq!(PersonEventTable::first_revertible(&tx, 4).unwrap(), 3);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_compact_drops_undo() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonEventTable::insert(&tx, 1, r#"{"1":{"name":"Ann"}}"#, Some(r#"{"1":null}"#)).is_ok());
+        assert!(PersonEventTable::insert(&tx, 2, r#"{"1":{"name":"Bob"}}"#, Some(r#"{"1":{"name":"Ann"}}"#)).is_ok());
+        assert!(PersonEventTable::insert(&tx, 3, r#"{"1":{"name":"Cam"}}"#, Some(r#"{"1":{"name":"Bob"}}"#)).is_ok());
+        assert_eq!(PersonEventTable::first_revertible(&tx, 3).unwrap(), 0);
+        assert_eq!(PersonEventTable::compact_before(&tx, 3).unwrap(), 1);
+        assert_eq!(PersonEventTable::first_revertible(&tx, 3).unwrap(), 2);
+        assert!(tx.commit().is_ok());
     }
 
     fn create_connection_and_table() -> Connection {
@@ -0,0 +1,92 @@
+use log::debug;
+use rusqlite::{Connection, OptionalExtension, params, Result, Transaction};
+use crate::domain::person_id::PersonId;
+
+// Holds the outcome of a request, so that a retry with the same key gets the same response
+const CREATE_IDEMPOTENCY_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS idempotency_key (
+        key TEXT NOT NULL PRIMARY KEY,
+        time INTEGER NOT NULL,
+        personId INTEGER NOT NULL,
+        revision INTEGER NOT NULL,
+        response TEXT NOT NULL
+    )";
+
+const INSERT_KEY: &str =
+    "INSERT INTO idempotency_key (key, time, personId, revision, response) VALUES (?, ?, ?, ?, ?)";
+
+const SELECT_KEY: &str =
+    "SELECT personId, revision, response FROM idempotency_key WHERE key = ?";
+
+const DELETE_KEYS: &str =
+    "DELETE FROM idempotency_key WHERE time < ?";
+
+///
+/// Stores the keys of header ``Idempotency-Key`` together with the person that was created
+/// and the serialized response. Keys must be stored in the transaction of the write they belong to.
+///
+pub struct IdempotencyTable;
+
+impl IdempotencyTable {
+    pub fn create_table(conn: &Connection) -> Result<()> {
+        debug!("Execute\n{}", CREATE_IDEMPOTENCY_TABLE);
+        conn.execute(CREATE_IDEMPOTENCY_TABLE, [])?;
+        Ok(())
+    }
+
+    pub fn insert(tx: &Transaction, key: &str, timestamp: u64, person_id: PersonId, revision: usize, response: &str) -> Result<()> {
+        debug!("Execute\n{} with: {}, {}, {}, {}, {}", INSERT_KEY, key, timestamp, person_id, revision, response);
+        tx.execute(INSERT_KEY, params![key, timestamp, person_id, revision, response])?;
+        Ok(())
+    }
+
+    /// Returns the person id, the revision, and the response stored with ``key``, if any.
+    pub fn select(tx: &Transaction, key: &str) -> Result<Option<(PersonId, usize, String)>> {
+        debug!("Execute\n{} with: {}", SELECT_KEY, key);
+        tx.query_row(SELECT_KEY, [key], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?))).optional()
+    }
+
+    /// Deletes all keys stored before ``timestamp`` and returns their number.
+    pub fn delete_before(tx: &Transaction, timestamp: u64) -> Result<usize> {
+        debug!("Execute\n{} with: {}", DELETE_KEYS, timestamp);
+        tx.execute(DELETE_KEYS, params![timestamp])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use crate::database::idempotency_table::IdempotencyTable;
+    use crate::domain::person_id::PersonId;
+
+    #[test]
+    fn test_insert_and_select() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(IdempotencyTable::insert(&tx, "abc", 1, PersonId::from(2), 3, "{}").is_ok());
+        assert!(IdempotencyTable::insert(&tx, "abc", 1, PersonId::from(4), 5, "{}").is_err());
+        assert_eq!(IdempotencyTable::select(&tx, "abc").unwrap(), Some((PersonId::from(2), 3, String::from("{}"))));
+        assert_eq!(IdempotencyTable::select(&tx, "xyz").unwrap(), None);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_delete_before() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(IdempotencyTable::insert(&tx, "abc", 1, PersonId::from(1), 1, "{}").is_ok());
+        assert!(IdempotencyTable::insert(&tx, "xyz", 2, PersonId::from(2), 2, "{}").is_ok());
+        assert_eq!(IdempotencyTable::delete_before(&tx, 2).unwrap(), 1);
+        assert_eq!(IdempotencyTable::select(&tx, "abc").unwrap(), None);
+        assert!(IdempotencyTable::select(&tx, "xyz").unwrap().is_some());
+        assert!(tx.commit().is_ok());
+    }
+
+    fn create_connection_and_table() -> Connection {
+        let conn = Connection::open(":memory:");
+        assert!(conn.is_ok());
+        let conn = conn.unwrap();
+        assert!(IdempotencyTable::create_table(&conn).is_ok());
+        conn
+    }
+}
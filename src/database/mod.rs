@@ -1,4 +1,7 @@
 pub mod person_table;
 pub mod revision_table;
 pub mod location_table;
-pub mod event_table;
\ No newline at end of file
+pub mod event_table;
+pub mod commit_table;
+pub mod snapshot_table;
+pub mod idempotency_table;
@@ -1,6 +1,7 @@
 use log::{debug, error};
 use rusqlite::{Connection, Error, OptionalExtension, params, Result, Row, ToSql, Transaction};
 use crate::domain::person_data::PersonData;
+use crate::domain::person_filter::PersonFilter;
 use crate::domain::person_id::PersonId;
 use crate::domain::person_map::PersonMap;
 use crate::domain::person_patch::PersonPatch;
@@ -10,22 +11,31 @@ const CREATE_PERSON_TABLE : &'static str =
         personId INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
         name TEXT NOT NULL,
         city TEXT,
-        spouse INTEGER
+        spouse INTEGER,
+        revision INTEGER NOT NULL DEFAULT 0
     )";
 
 const INSERT_PERSON : &'static str =
     "INSERT INTO person (name, city, spouse) VALUES (?, ?, ?)";
 
+const INSERT_PERSON_WITH_ID : &str =
+    "INSERT INTO person (personId, name, city, spouse) VALUES (?, ?, ?, ?)";
+
 const DELETE_PERSON : &'static str =
     "DELETE FROM person WHERE personId = ?";
 
 const SELECT_PERSONS : &'static str =
     "SELECT personId, name, city, spouse FROM person";
 
+const UPDATE_REVISION : &str =
+    "UPDATE person SET revision = ? WHERE personId = ?";
+
+const SELECT_REVISION : &str =
+    "SELECT revision FROM person WHERE personId = ?";
+
 const SELECT_PERSON : &'static str =
     "SELECT personId, name, city, spouse FROM person WHERE personId = ?";
 
-
 pub struct PersonTable;
 
 impl PersonTable {
@@ -42,6 +52,14 @@ impl PersonTable {
         Ok(PersonId::from(tx.last_insert_rowid() as u64))
     }
 
+    /// Inserts a person with an id chosen by the client. Fails if the id is already taken.
+    pub fn insert_with_id(tx: &Transaction, person_id: PersonId, person: &PersonData) -> Result<PersonId> {
+        debug!("Execute\n{}\nwith: {}, {:?}", INSERT_PERSON_WITH_ID, person_id, person);
+        let values = params![person_id, person.name, person.city, person.spouse];
+        tx.execute(INSERT_PERSON_WITH_ID, values)?;
+        Ok(person_id)
+    }
+
     pub fn update(tx: &Transaction, person_id: PersonId, person: &PersonPatch) -> Result<PersonData> {
         let mut columns = Vec::new();
         let mut values: Vec<&dyn ToSql> = Vec::new();
@@ -74,10 +92,52 @@ impl PersonTable {
         Ok(row_count == 1)
     }
 
+    /// Sets the revision of the last event that changed ``person_id``.
+    pub fn update_revision(tx: &Transaction, person_id: PersonId, revision: usize) -> Result<()> {
+        debug!("Execute\n{} with: {}, {}", UPDATE_REVISION, revision, person_id);
+        tx.execute(UPDATE_REVISION, params![revision, person_id])?;
+        Ok(())
+    }
+
+    /// Returns the revision of the last event that changed ``person_id``, or ``None`` if the person does not exist.
+    /// Ids above [PersonId::MAX] cannot be stored, so they never exist.
+    pub fn select_revision(tx: &Transaction, person_id: PersonId) -> Result<Option<usize>> {
+        if person_id > PersonId::MAX {
+            return Ok(None)
+        }
+        debug!("Execute\n{} with: {}", SELECT_REVISION, person_id);
+        tx.query_row(SELECT_REVISION, [person_id], |row| row.get(0)).optional()
+    }
+
     pub fn select_all(tx: &Transaction) -> Result<PersonMap> {
-        debug!("Execute\n{}", SELECT_PERSONS);
-        let mut stmt = tx.prepare(SELECT_PERSONS)?;
-        let rows = stmt.query_map([], |row| {
+        Self::select_filtered(tx, &PersonFilter::default())
+    }
+
+    pub fn select_filtered(tx: &Transaction, filter: &PersonFilter) -> Result<PersonMap> {
+        let mut conditions = Vec::new();
+        let mut values: Vec<&dyn ToSql> = Vec::new();
+        if let Some(city) = filter.city.as_ref() {
+            conditions.push("city = ?");
+            values.push(city);
+        }
+        let name_pattern = filter.name.as_ref().map(|name| Self::prefix_pattern(name));
+        if let Some(pattern) = name_pattern.as_ref() {
+            conditions.push("name LIKE ? ESCAPE '\\'");
+            values.push(pattern);
+        }
+        match filter.married {
+            Some(true) => conditions.push("spouse IS NOT NULL"),
+            Some(false) => conditions.push("spouse IS NULL"),
+            None => {}
+        }
+        let query = if conditions.is_empty() {
+            String::from(SELECT_PERSONS)
+        } else {
+            format!("{} WHERE {}", SELECT_PERSONS, conditions.join(" AND "))
+        };
+        debug!("Execute\n{}\nwith: {:?}", query, filter);
+        let mut stmt = tx.prepare(query.as_str())?;
+        let rows = stmt.query_map(values.as_slice(), |row| {
             Self::row_to_person_data(row)
         })?;
         let mut person_map = PersonMap::new();
@@ -100,6 +160,12 @@ impl PersonTable {
         })
     }
 
+    // Escapes the wildcards of LIKE, so that the name is matched literally
+    fn prefix_pattern(name: &str) -> String {
+        let name = name.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        format!("{}%", name)
+    }
+
     fn row_to_person_data(row: &Row) -> Result<(PersonId, PersonData)> {
         Ok((row.get(0)?, PersonData {
             name: row.get(1)?,
@@ -114,6 +180,7 @@ mod tests {
     use rusqlite::Connection;
     use crate::database::person_table::PersonTable;
     use crate::domain::person_data::PersonData;
+    use crate::domain::person_filter::PersonFilter;
     use crate::domain::person_id::PersonId;
     use crate::domain::person_patch::PersonPatch;
     use crate::util::patch::Patch;
@@ -171,6 +238,30 @@ mod tests {
         assert!(PersonTable::update(&tx, PersonId::from(1), &person_update).is_err());
     }
 
+    #[test]
+    fn test_insert_with_id() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        let person = PersonData::new("Ann", None, None);
+        assert_eq!(PersonTable::insert_with_id(&tx, PersonId::from(5), &person).unwrap(), PersonId::from(5));
+        assert!(PersonTable::insert_with_id(&tx, PersonId::from(5), &person).is_err());
+        // Server-assigned ids continue after client-chosen ids
+        assert_eq!(PersonTable::insert(&tx, &person).unwrap(), PersonId::from(6));
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_update_revision() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonTable::insert(&tx, &PersonData::new("Ann", None, None)).is_ok());
+        assert_eq!(PersonTable::select_revision(&tx, PersonId::from(1)).unwrap(), Some(0));
+        assert!(PersonTable::update_revision(&tx, PersonId::from(1), 5).is_ok());
+        assert_eq!(PersonTable::select_revision(&tx, PersonId::from(1)).unwrap(), Some(5));
+        assert_eq!(PersonTable::select_revision(&tx, PersonId::from(2)).unwrap(), None);
+        assert!(tx.commit().is_ok());
+    }
+
     #[test]
     fn test_delete() {
         let person = PersonData::new("Ann", Some("here"), Some(PersonId::from(123)));
@@ -196,6 +287,30 @@ mod tests {
         assert!(tx.commit().is_ok());
     }
 
+    #[test]
+    fn test_select_filtered() {
+        let mut conn = create_connection_and_table();
+        let tx = conn.transaction().unwrap();
+        assert!(PersonTable::insert(&tx, &PersonData::new("Ann", Some("Rome"), Some(PersonId::from(2)))).is_ok());
+        assert!(PersonTable::insert(&tx, &PersonData::new("Andy", Some("Rome"), None)).is_ok());
+        assert!(PersonTable::insert(&tx, &PersonData::new("Bob", Some("Paris"), Some(PersonId::from(1)))).is_ok());
+        assert!(PersonTable::insert(&tx, &PersonData::new("A_x", None, None)).is_ok());
+
+        let select = |city: Option<&str>, name: Option<&str>, married: Option<bool>| {
+            let filter = PersonFilter { city: city.map(String::from), name: name.map(String::from), married };
+            let persons = PersonTable::select_filtered(&tx, &filter).unwrap();
+            (1..=4).map(PersonId::from).filter(|id| persons.contains(*id)).collect::<Vec<PersonId>>()
+        };
+        assert_eq!(select(Some("Rome"), None, None), vec![PersonId::from(1), PersonId::from(2)]);
+        assert_eq!(select(Some("Rome"), None, Some(true)), vec![PersonId::from(1)]);
+        assert_eq!(select(None, None, Some(false)), vec![PersonId::from(2), PersonId::from(4)]);
+        assert_eq!(select(None, Some("an"), None), vec![PersonId::from(1), PersonId::from(2)]);
+        assert_eq!(select(None, Some("A_"), None), vec![PersonId::from(4)]); // No wildcard
+        assert_eq!(select(Some("Paris"), Some("Ann"), None), vec![]);
+        assert_eq!(select(None, None, None).len(), 4);
+        assert!(tx.commit().is_ok());
+    }
+
     fn create_connection_and_table() -> Connection {
         let conn = Connection::open(":memory:");
         assert!(conn.is_ok());
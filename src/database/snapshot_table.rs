@@ -0,0 +1,285 @@
+use log::debug;
+use rusqlite::{Connection, OptionalExtension, params, Result, Transaction};
+use crate::domain::person_data::PersonData;
+use crate::domain::person_id::PersonId;
+
+// Persons with ids above max_person_id were created after the snapshot was taken
+const CREATE_SNAPSHOT_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS person_snapshot (
+        snapshot INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+        time INTEGER NOT NULL,
+        revision INTEGER NOT NULL,
+        commit_id INTEGER NOT NULL,
+        max_person_id INTEGER NOT NULL
+    )";
+
+// Holds the state of a person at snapshot time, if the person was changed or deleted since.
+// Rows without name mark persons that did not exist at snapshot time, but were created with a lower id.
+const CREATE_SNAPSHOT_ROW_TABLE: &str =
+    "CREATE TABLE IF NOT EXISTS person_snapshot_row (
+        snapshot INTEGER NOT NULL,
+        personId INTEGER NOT NULL,
+        name TEXT,
+        city TEXT,
+        spouse INTEGER,
+        PRIMARY KEY (snapshot, personId)
+    )";
+
+const INSERT_SNAPSHOT: &str =
+    "INSERT INTO person_snapshot (time, revision, commit_id, max_person_id)
+      SELECT ?, ?, ?, COALESCE(MAX(personId), 0) FROM person";
+
+const SELECT_SNAPSHOT: &str =
+    "SELECT revision, commit_id FROM person_snapshot WHERE snapshot = ?";
+
+const SELECT_LATEST_SNAPSHOT: &str =
+    "SELECT snapshot, revision, commit_id FROM person_snapshot ORDER BY snapshot DESC LIMIT 1";
+
+const TOUCH_SNAPSHOT: &str =
+    "UPDATE person_snapshot SET time = ? WHERE snapshot = ?";
+
+// Only the first change of a person after a snapshot saves its state, later ones are ignored
+const SAVE_PERSON: &str =
+    "INSERT OR IGNORE INTO person_snapshot_row (snapshot, personId, name, city, spouse)
+      SELECT s.snapshot, p.personId, p.name, p.city, p.spouse FROM person_snapshot s, person p
+      WHERE p.personId = ? AND p.personId <= s.max_person_id";
+
+const SAVE_ABSENT_PERSON: &str =
+    "INSERT OR IGNORE INTO person_snapshot_row (snapshot, personId)
+      SELECT snapshot, ?1 FROM person_snapshot WHERE ?1 <= max_person_id";
+
+const SELECT_PAGE: &str =
+    "SELECT personId, name, city, spouse FROM person_snapshot_row
+      WHERE snapshot = ?1 AND personId > ?2 AND name IS NOT NULL
+    UNION ALL
+    SELECT personId, name, city, spouse FROM person
+      WHERE personId > ?2 AND personId <= (SELECT max_person_id FROM person_snapshot WHERE snapshot = ?1)
+      AND personId NOT IN (SELECT personId FROM person_snapshot_row WHERE snapshot = ?1)
+    ORDER BY personId LIMIT ?3";
+
+const DELETE_SNAPSHOT_ROWS: &str =
+    "DELETE FROM person_snapshot_row WHERE snapshot IN (SELECT snapshot FROM person_snapshot WHERE time < ?)";
+
+const DELETE_SNAPSHOTS: &str =
+    "DELETE FROM person_snapshot WHERE time < ?";
+
+///
+/// Keeps the state of the person table at the time a snapshot was taken, so that the persons
+/// can be read page by page in the state of one revision. Instead of copying the whole table,
+/// the state of a person is saved only when the person is changed or deleted after the snapshot.
+/// Callers must therefore call [save](Self::save) before every update or deletion of a person,
+/// and before every creation of a person with an id chosen by the client.
+///
+pub struct PersonSnapshotTable;
+
+impl PersonSnapshotTable {
+    pub fn create_table(conn: &Connection) -> Result<()> {
+        debug!("Execute\n{}", CREATE_SNAPSHOT_TABLE);
+        conn.execute(CREATE_SNAPSHOT_TABLE, [])?;
+        debug!("Execute\n{}", CREATE_SNAPSHOT_ROW_TABLE);
+        conn.execute(CREATE_SNAPSHOT_ROW_TABLE, [])?;
+        Ok(())
+    }
+
+    /// Takes a snapshot of the current persons and returns its id.
+    pub fn insert(tx: &Transaction, timestamp: u64, revision: usize, commit_id: usize) -> Result<usize> {
+        debug!("Execute\n{} with: {}, {}, {}", INSERT_SNAPSHOT, timestamp, revision, commit_id);
+        tx.execute(INSERT_SNAPSHOT, params![timestamp, revision, commit_id])?;
+        Ok(tx.last_insert_rowid() as usize)
+    }
+
+    /// Returns the person revision and the commit id of ``snapshot``, or ``None`` if it does not exist.
+    pub fn select(tx: &Transaction, snapshot: usize) -> Result<Option<(usize, usize)>> {
+        debug!("Execute\n{} with: {}", SELECT_SNAPSHOT, snapshot);
+        tx.query_row(SELECT_SNAPSHOT, [snapshot], |row| Ok((row.get(0)?, row.get(1)?))).optional()
+    }
+
+    ///
+    /// Returns the latest snapshot if it was taken at ``revision`` and ``commit_id``, so that it still holds
+    /// the current persons, and renews its time. Otherwise returns ``None``.
+    ///
+    pub fn reuse(tx: &Transaction, timestamp: u64, revision: usize, commit_id: usize) -> Result<Option<usize>> {
+        debug!("Execute\n{}", SELECT_LATEST_SNAPSHOT);
+        let latest = tx.query_row(SELECT_LATEST_SNAPSHOT, [], |row| {
+            Ok((row.get::<_, usize>(0)?, row.get::<_, usize>(1)?, row.get::<_, usize>(2)?))
+        }).optional()?;
+        match latest {
+            Some((snapshot, latest_revision, latest_commit_id)) if latest_revision == revision && latest_commit_id == commit_id => {
+                debug!("Execute\n{} with: {}, {}", TOUCH_SNAPSHOT, timestamp, snapshot);
+                tx.execute(TOUCH_SNAPSHOT, params![timestamp, snapshot])?;
+                Ok(Some(snapshot))
+            },
+            _ => Ok(None)
+        }
+    }
+
+    /// Saves the current state of ``person_id`` for all snapshots that do not hold it yet.
+    /// If the person does not exist, the snapshots remember that it was absent.
+    pub fn save(tx: &Transaction, person_id: PersonId) -> Result<()> {
+        debug!("Execute\n{} with: {}", SAVE_PERSON, person_id);
+        tx.execute(SAVE_PERSON, params![person_id])?;
+        debug!("Execute\n{} with: {}", SAVE_ABSENT_PERSON, person_id);
+        tx.execute(SAVE_ABSENT_PERSON, params![person_id])?;
+        Ok(())
+    }
+
+    /// Returns at most ``limit`` persons of ``snapshot`` with ids greater than ``after``, ordered by id.
+    pub fn select_page(tx: &Transaction, snapshot: usize, after: PersonId, limit: usize) -> Result<Vec<(PersonId, PersonData)>> {
+        debug!("Execute\n{} with: {}, {}, {}", SELECT_PAGE, snapshot, after, limit);
+        let mut stmt = tx.prepare(SELECT_PAGE)?;
+        let rows = stmt.query_map(params![snapshot, after, limit], |row| {
+            Ok((row.get(0)?, PersonData { name: row.get(1)?, city: row.get(2)?, spouse: row.get(3)? }))
+        })?;
+        let mut persons = Vec::new();
+        for row in rows {
+            persons.push(row?);
+        }
+        Ok(persons)
+    }
+
+    /// Deletes all snapshots taken before ``timestamp`` and returns their number.
+    pub fn delete_before(tx: &Transaction, timestamp: u64) -> Result<usize> {
+        debug!("Execute\n{} with: {}", DELETE_SNAPSHOT_ROWS, timestamp);
+        tx.execute(DELETE_SNAPSHOT_ROWS, params![timestamp])?;
+        debug!("Execute\n{} with: {}", DELETE_SNAPSHOTS, timestamp);
+        tx.execute(DELETE_SNAPSHOTS, params![timestamp])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::{Connection, Transaction};
+    use crate::database::person_table::PersonTable;
+    use crate::database::snapshot_table::PersonSnapshotTable;
+    use crate::domain::person_data::PersonData;
+    use crate::domain::person_id::PersonId;
+    use crate::domain::person_patch::PersonPatch;
+    use crate::util::patch::Patch;
+
+    #[test]
+    fn test_insert_and_select() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        assert_eq!(PersonSnapshotTable::insert(&tx, 1, 5, 3).unwrap(), 1);
+        assert_eq!(PersonSnapshotTable::insert(&tx, 1, 6, 4).unwrap(), 2);
+        assert_eq!(PersonSnapshotTable::select(&tx, 1).unwrap(), Some((5, 3)));
+        assert_eq!(PersonSnapshotTable::select(&tx, 3).unwrap(), None);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_reuse() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        assert_eq!(PersonSnapshotTable::reuse(&tx, 1, 5, 3).unwrap(), None);
+        assert_eq!(PersonSnapshotTable::insert(&tx, 1, 5, 3).unwrap(), 1);
+        assert_eq!(PersonSnapshotTable::reuse(&tx, 2, 5, 3).unwrap(), Some(1));
+        assert_eq!(PersonSnapshotTable::reuse(&tx, 2, 6, 4).unwrap(), None);
+        // The reused snapshot expires later
+        assert_eq!(PersonSnapshotTable::delete_before(&tx, 2).unwrap(), 0);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_select_page() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        insert_persons(&tx, &["Ann", "Bob", "Cat"]);
+        let snapshot = PersonSnapshotTable::insert(&tx, 1, 3, 3).unwrap();
+
+        let page = PersonSnapshotTable::select_page(&tx, snapshot, PersonId::from(0), 2).unwrap();
+        assert_eq!(page, vec![
+            (PersonId::from(1), PersonData::new("Ann", None, None)),
+            (PersonId::from(2), PersonData::new("Bob", None, None))
+        ]);
+        let page = PersonSnapshotTable::select_page(&tx, snapshot, PersonId::from(2), 2).unwrap();
+        assert_eq!(page, vec![(PersonId::from(3), PersonData::new("Cat", None, None))]);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_select_page_after_changes() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        insert_persons(&tx, &["Ann", "Bob"]);
+        let snapshot = PersonSnapshotTable::insert(&tx, 1, 2, 2).unwrap();
+
+        // Change Ann twice, delete Bob, and create Cat
+        let patch = PersonPatch::new(None, Patch::Value("Rome"), Patch::Absent);
+        assert!(PersonSnapshotTable::save(&tx, PersonId::from(1)).is_ok());
+        assert!(PersonTable::update(&tx, PersonId::from(1), &patch).is_ok());
+        let patch = PersonPatch::new(Some("Eve"), Patch::Absent, Patch::Absent);
+        assert!(PersonSnapshotTable::save(&tx, PersonId::from(1)).is_ok());
+        assert!(PersonTable::update(&tx, PersonId::from(1), &patch).is_ok());
+        assert!(PersonSnapshotTable::save(&tx, PersonId::from(2)).is_ok());
+        assert!(PersonTable::delete(&tx, PersonId::from(2)).is_ok());
+        insert_persons(&tx, &["Cat"]);
+        assert!(PersonSnapshotTable::save(&tx, PersonId::from(3)).is_ok());
+
+        let page = PersonSnapshotTable::select_page(&tx, snapshot, PersonId::from(0), 10).unwrap();
+        assert_eq!(page, vec![
+            (PersonId::from(1), PersonData::new("Ann", None, None)),
+            (PersonId::from(2), PersonData::new("Bob", None, None))
+        ]);
+
+        // A later snapshot sees the changes
+        let snapshot = PersonSnapshotTable::insert(&tx, 1, 5, 5).unwrap();
+        let page = PersonSnapshotTable::select_page(&tx, snapshot, PersonId::from(0), 10).unwrap();
+        assert_eq!(page, vec![
+            (PersonId::from(1), PersonData::new("Eve", Some("Rome"), None)),
+            (PersonId::from(3), PersonData::new("Cat", None, None))
+        ]);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_select_page_after_insert_with_id() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        insert_persons(&tx, &["Ann", "Bob", "Cat"]);
+        assert!(PersonTable::delete(&tx, PersonId::from(2)).is_ok());
+        let snapshot = PersonSnapshotTable::insert(&tx, 1, 4, 4).unwrap();
+
+        // Recreate Bob with his old id
+        assert!(PersonSnapshotTable::save(&tx, PersonId::from(2)).is_ok());
+        assert!(PersonTable::insert_with_id(&tx, PersonId::from(2), &PersonData::new("Bob", None, None)).is_ok());
+
+        let page = PersonSnapshotTable::select_page(&tx, snapshot, PersonId::from(0), 10).unwrap();
+        assert_eq!(page, vec![
+            (PersonId::from(1), PersonData::new("Ann", None, None)),
+            (PersonId::from(3), PersonData::new("Cat", None, None))
+        ]);
+        assert!(tx.commit().is_ok());
+    }
+
+    #[test]
+    fn test_delete_before() {
+        let mut conn = create_connection_and_tables();
+        let tx = conn.transaction().unwrap();
+        insert_persons(&tx, &["Ann"]);
+        let snapshot1 = PersonSnapshotTable::insert(&tx, 1, 1, 1).unwrap();
+        let snapshot2 = PersonSnapshotTable::insert(&tx, 2, 1, 1).unwrap();
+        assert!(PersonSnapshotTable::save(&tx, PersonId::from(1)).is_ok());
+        assert_eq!(PersonSnapshotTable::delete_before(&tx, 2).unwrap(), 1);
+        assert_eq!(PersonSnapshotTable::select(&tx, snapshot1).unwrap(), None);
+        assert_eq!(PersonSnapshotTable::select(&tx, snapshot2).unwrap(), Some((1, 1)));
+        let rows: usize = tx.query_row("SELECT COUNT(*) FROM person_snapshot_row", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 1);
+        assert!(tx.commit().is_ok());
+    }
+
+    fn insert_persons(tx: &Transaction, names: &[&str]) {
+        for name in names {
+            assert!(PersonTable::insert(tx, &PersonData::new(name, None, None)).is_ok());
+        }
+    }
+
+    fn create_connection_and_tables() -> Connection {
+        let conn = Connection::open(":memory:");
+        assert!(conn.is_ok());
+        let conn = conn.unwrap();
+        assert!(PersonTable::create_table(&conn).is_ok());
+        assert!(PersonSnapshotTable::create_table(&conn).is_ok());
+        conn
+    }
+}
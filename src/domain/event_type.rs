@@ -1,5 +1,18 @@
-#[derive(Debug,Copy,Clone)]
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug,Copy,Clone,Hash,Eq,PartialEq,Serialize,Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum EventType {
     PERSON = 1,
     LOCATION = 2
+}
+
+impl EventType {
+    /// Returns the lowercase name, as used in serialized messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::PERSON => "person",
+            EventType::LOCATION => "location"
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,54 @@
+use serde::Serialize;
+
+///
+/// A problem with one field of a request body, found by
+/// [PersonValidator](crate::aggregator::person_validator::PersonValidator).
+/// Field ``code`` is meant for machines, ``message`` for humans. Errors that concern
+/// the body as a whole, like an empty patch, have no ``field``.
+///
+#[derive(Clone, Serialize, Debug, Eq, PartialEq)]
+pub struct FieldError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<&'static str>,
+    pub code: &'static str,
+    pub message: String
+}
+
+/// The body does not hold any change
+pub const EMPTY: &str = "empty";
+/// The value consists of whitespace only
+pub const BLANK: &str = "blank";
+/// The person refers to itself
+pub const SELF_REFERENCE: &str = "self_reference";
+/// The referenced person does not exist
+pub const NOT_FOUND: &str = "not_found";
+/// The value is outside of the supported range
+pub const OUT_OF_RANGE: &str = "out_of_range";
+
+impl FieldError {
+    pub fn new(field: Option<&'static str>, code: &'static str, message: &str) -> Self {
+        Self { field, code, message: String::from(message) }
+    }
+
+    /// Tells whether the request is malformed as a whole rather than having invalid values.
+    pub fn is_malformed(&self) -> bool {
+        self.code == EMPTY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::field_error::{BLANK, EMPTY, FieldError};
+
+    #[test]
+    fn test_serialize() {
+        let error = FieldError::new(Some("name"), BLANK, "Name must not be blank");
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(json, r#"{"field":"name","code":"blank","message":"Name must not be blank"}"#);
+
+        let error = FieldError::new(None, EMPTY, "Patch must change at least one field");
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(json, r#"{"code":"empty","message":"Patch must change at least one field"}"#);
+        assert!(error.is_malformed());
+    }
+}
@@ -1,9 +1,13 @@
 pub mod event_type;
+pub mod field_error;
 pub mod person_id;
 pub mod person_data;
 pub mod person_patch;
 pub mod person_event;
 pub mod person_map;
+pub mod person_filter;
+pub mod person_operation;
+pub mod precondition;
 pub mod location_data;
 pub mod location_patch;
 pub mod location_event;
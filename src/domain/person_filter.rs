@@ -0,0 +1,11 @@
+///
+/// Predicates on the persons selected by [PersonTable::select_filtered](crate::database::person_table::PersonTable::select_filtered).
+/// Field ``name`` is a prefix of the name, which is matched case-insensitively for ASCII letters.
+/// Field ``married`` selects persons with (``true``) or without (``false``) spouse.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PersonFilter {
+    pub city: Option<String>,
+    pub name: Option<String>,
+    pub married: Option<bool>
+}
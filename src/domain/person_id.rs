@@ -12,6 +12,11 @@ use serde::{Serialize, Deserialize};
 #[derive(Clone, Copy, Hash, Serialize, Deserialize, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub struct PersonId(u64);
 
+impl PersonId {
+    /// The largest id that fits into an SQLite integer column
+    pub const MAX: PersonId = PersonId(i64::MAX as u64);
+}
+
 impl From<u64> for PersonId {
     fn from(value: u64) -> Self {
         PersonId { 0: value }
@@ -24,6 +24,10 @@ impl PersonMap {
         self.0.len()
     }
 
+    pub fn contains(&self, person_id: PersonId) -> bool {
+        self.0.contains_key(&person_id)
+    }
+
     pub fn get(&self, person_id: PersonId) -> &PersonData {
         self.0.get(&person_id).unwrap() // Panic accepted
     }
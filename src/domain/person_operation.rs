@@ -0,0 +1,51 @@
+use serde::{Serialize, Deserialize};
+use crate::domain::person_data::PersonData;
+use crate::domain::person_id::PersonId;
+use crate::domain::person_patch::PersonPatch;
+
+///
+/// One write operation of a batch as received via ``POST /persons/batch``.
+/// The operations of a batch are applied in one transaction, see
+/// [apply_batch](crate::aggregator::aggregator_facade::AggregatorFacade::apply_batch).
+///
+/// Field ``op`` tells the kind of operation, for example ``{"op":"patch","id":1,"patch":{"city":null}}``.
+///
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PersonOperation {
+    Insert { person: PersonData },
+    Patch { id: PersonId, patch: PersonPatch },
+    Delete { id: PersonId }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::person_data::PersonData;
+    use crate::domain::person_id::PersonId;
+    use crate::domain::person_operation::PersonOperation;
+    use crate::domain::person_patch::PersonPatch;
+    use crate::util::patch::Patch;
+    use crate::util::serde_and_verify::tests::serde_and_verify;
+
+    #[test]
+    fn test_serde_insert() {
+        let operation = PersonOperation::Insert { person: PersonData::new("Ann", Some("here"), None) };
+        let json_ref = r#"{"op":"insert","person":{"name":"Ann","city":"here"}}"#;
+        serde_and_verify(&operation, json_ref);
+    }
+
+    #[test]
+    fn test_serde_patch() {
+        let patch = PersonPatch::new(None, Patch::Null, Patch::Absent);
+        let operation = PersonOperation::Patch { id: PersonId::from(1), patch };
+        let json_ref = r#"{"op":"patch","id":1,"patch":{"city":null}}"#;
+        serde_and_verify(&operation, json_ref);
+    }
+
+    #[test]
+    fn test_serde_delete() {
+        let operation = PersonOperation::Delete { id: PersonId::from(1) };
+        let json_ref = r#"{"op":"delete","id":1}"#;
+        serde_and_verify(&operation, json_ref);
+    }
+}
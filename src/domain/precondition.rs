@@ -0,0 +1,13 @@
+///
+/// The precondition of a person write, as stated by header ``If-Match``. Preconditions other than
+/// ``Unconditional`` require that the person exists, so a missing person fails them (RFC 9110).
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Precondition {
+    /// The person is written whether it exists or not, no header was given
+    Unconditional,
+    /// The person must exist, with any revision (``If-Match: *``)
+    Exists,
+    /// The person must exist and its revision must be one of these revisions
+    Revisions(Vec<usize>)
+}
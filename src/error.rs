@@ -0,0 +1,103 @@
+use std::fmt;
+use rusqlite::ErrorCode;
+use crate::domain::field_error::FieldError;
+
+///
+/// The error of all operations of the [AggregatorFacade](crate::aggregator::aggregator_facade::AggregatorFacade).
+/// Its variants separate the kinds of failure that clients can act upon, the REST layer maps them to
+/// HTTP statuses and problem details (RFC 7807), see [Problem](crate::rest::problem::Problem).
+/// Database errors are converted by ``?``, constraint violations are told apart from other storage failures.
+///
+#[derive(Debug, PartialEq)]
+pub enum DomainError {
+    /// The person does not exist, holds a description
+    NotFound(String),
+    /// The request contradicts an earlier request, holds a description
+    Conflict(String),
+    /// The revision of the person is not one of the expected revisions, holds a description
+    /// and the current revision of the person, if it exists
+    PreconditionFailed(String, Option<usize>),
+    /// The request data is invalid, holds the problems
+    Validation(Vec<FieldError>),
+    /// The database refused a write that violates a constraint, holds the message of the database
+    Constraint(String),
+    /// The database failed
+    Storage(rusqlite::Error),
+    /// The operation at this index of a batch failed, so no operation was applied
+    Operation(usize, Box<DomainError>)
+}
+
+pub type Result<T> = std::result::Result<T, DomainError>;
+
+impl DomainError {
+    /// Returns the error that caused a failed batch, or this error if it does not belong to a batch.
+    pub fn cause(&self) -> &DomainError {
+        match self {
+            DomainError::Operation(_, cause) => cause.cause(),
+            error => error
+        }
+    }
+}
+
+impl fmt::Display for DomainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainError::NotFound(message) | DomainError::Conflict(message) | DomainError::PreconditionFailed(message, _) => {
+                write!(f, "{}", message)
+            },
+            DomainError::Validation(errors) => {
+                let messages: Vec<&str> = errors.iter().map(|error| error.message.as_str()).collect();
+                write!(f, "Invalid person data: {}", messages.join(", "))
+            },
+            DomainError::Constraint(message) => write!(f, "Constraint violated: {}", message),
+            DomainError::Storage(error) => write!(f, "Database failed: {}", error),
+            DomainError::Operation(index, cause) => write!(f, "Operation {} failed, no operation was applied: {}", index, cause)
+        }
+    }
+}
+
+impl std::error::Error for DomainError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DomainError::Storage(error) => Some(error),
+            DomainError::Operation(_, cause) => Some(cause.as_ref()),
+            _ => None
+        }
+    }
+}
+
+impl From<rusqlite::Error> for DomainError {
+    fn from(error: rusqlite::Error) -> Self {
+        match error.sqlite_error_code() {
+            Some(ErrorCode::ConstraintViolation) => DomainError::Constraint(error.to_string()),
+            _ => DomainError::Storage(error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use crate::domain::field_error::{BLANK, FieldError};
+    use crate::error::DomainError;
+
+    #[test]
+    fn test_from_rusqlite_error() {
+        let conn = Connection::open(":memory:").unwrap();
+        conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY)", []).unwrap();
+        conn.execute("INSERT INTO t (id) VALUES (1)", []).unwrap();
+        let error = DomainError::from(conn.execute("INSERT INTO t (id) VALUES (1)", []).unwrap_err());
+        assert!(matches!(error, DomainError::Constraint(_)));
+        let error = DomainError::from(conn.execute("SELECT * FROM missing", []).unwrap_err());
+        assert!(matches!(error, DomainError::Storage(_)));
+    }
+
+    #[test]
+    fn test_display() {
+        let error = DomainError::Validation(vec![FieldError::new(Some("name"), BLANK, "Name must not be blank")]);
+        assert_eq!(error.to_string(), "Invalid person data: Name must not be blank");
+        let error = DomainError::Operation(2, Box::new(DomainError::NotFound(String::from("Person 5 not found"))));
+        assert_eq!(error.to_string(), "Operation 2 failed, no operation was applied: Person 5 not found");
+        assert_eq!(error.cause(), &DomainError::NotFound(String::from("Person 5 not found")));
+    }
+}
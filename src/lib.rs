@@ -2,4 +2,5 @@ mod domain;
 mod database;
 pub mod util;
 pub mod rest;
-pub mod aggregator;
\ No newline at end of file
+pub mod aggregator;
+pub mod error;
@@ -0,0 +1,227 @@
+use std::fmt;
+use serde::Serialize;
+use warp::reject::Reject;
+use warp::reply;
+
+///
+/// The media types of aggregates and event pages, negotiated by header ``Accept``.
+/// All of them reuse the serde derives of the domain objects. In particular, absent fields
+/// of a [Patch](crate::util::patch::Patch) are skipped and ``null`` values are encoded as
+/// ``null`` (CBOR) or ``nil`` (MessagePack), so the merge-patch semantics are the same.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Json,
+    Cbor,
+    MessagePack
+}
+
+impl Encoding {
+    ///
+    /// Returns the supported media type of an ``Accept`` header with the highest quality value,
+    /// the first one of several with the same quality. Media types with quality 0 are not acceptable.
+    ///
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        Self::negotiate_quality(accept).map(|(encoding, _)| encoding)
+    }
+
+    ///
+    /// Like [negotiate](Self::negotiate), but returns ``None`` if the ``Accept`` header prefers
+    /// media type ``alternative``, which the endpoint also serves, over all supported media types.
+    ///
+    pub fn negotiate_against(accept: &str, alternative: &str) -> Option<Self> {
+        let alternative_quality = media_ranges(accept)
+            .filter(|(media_type, _)| media_type == alternative)
+            .fold(0.0, |best, (_, quality)| quality.max(best));
+        Self::negotiate_quality(accept)
+            .filter(|(_, quality)| *quality >= alternative_quality)
+            .map(|(encoding, _)| encoding)
+    }
+
+    fn negotiate_quality(accept: &str) -> Option<(Self, f32)> {
+        media_ranges(accept)
+            .filter_map(|(media_type, quality)| Self::of(&media_type).map(|encoding| (encoding, quality)))
+            .fold(None, |best, (encoding, quality)| match best {
+                Some((_, best_quality)) if best_quality >= quality => best,
+                _ if quality > 0.0 => Some((encoding, quality)),
+                _ => best
+            })
+    }
+
+    fn of(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" => Some(Encoding::Json),
+            "application/cbor" => Some(Encoding::Cbor),
+            "application/msgpack" | "application/x-msgpack" | "application/vnd.msgpack" => Some(Encoding::MessagePack),
+            _ => None
+        }
+    }
+
+    ///
+    /// Selects the encoding of a response like [negotiate](Self::negotiate). Requests without ``Accept``
+    /// header or with a wildcard like ``*/*`` get JSON, unless the header excludes JSON with quality 0.
+    /// Other requests are rejected with [NotAcceptable].
+    ///
+    pub fn select(accept: Option<&str>) -> Result<Self, NotAcceptable> {
+        match accept {
+            None => Ok(Encoding::Json),
+            Some(accept) => Self::negotiate(accept)
+                .or_else(|| accepts_json_by_wildcard(accept).then_some(Encoding::Json))
+                .ok_or_else(|| NotAcceptable(String::from(accept)))
+        }
+    }
+
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::Cbor => "application/cbor",
+            Encoding::MessagePack => "application/msgpack"
+        }
+    }
+
+    /// Distinguishes the entity tags of the representations of one revision.
+    pub fn tag_suffix(&self) -> &'static str {
+        match self {
+            Encoding::Json => "",
+            Encoding::Cbor => "-cbor",
+            Encoding::MessagePack => "-msgpack"
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Vec<u8> {
+        // Domain objects are plain data, errors should not happen, panic accepted
+        match self {
+            Encoding::Json => serde_json::to_vec(value).unwrap(),
+            Encoding::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(value, &mut bytes).unwrap();
+                bytes
+            },
+            // Structs must be maps rather than arrays, otherwise skipped fields would shift the others
+            Encoding::MessagePack => rmp_serde::to_vec_named(value).unwrap()
+        }
+    }
+
+    /// Replies with the encoded ``value`` and the matching ``Content-Type``.
+    pub fn reply<T: Serialize>(&self, value: &T) -> reply::WithHeader<Vec<u8>> {
+        reply::with_header(self.encode(value), "Content-Type", self.media_type())
+    }
+}
+
+// The media types of the media ranges of an Accept header together with their quality values,
+// 1 if there is none. Media ranges with an invalid quality value are skipped.
+fn media_ranges(accept: &str) -> impl Iterator<Item = (String, f32)> + '_ {
+    accept.split(',').filter_map(|media_range| {
+        let mut parts = media_range.split(';');
+        let media_type = parts.next()?.trim().to_ascii_lowercase();
+        let mut quality = 1.0;
+        for (name, value) in parts.filter_map(|parameter| parameter.split_once('=')) {
+            if name.trim().eq_ignore_ascii_case("q") {
+                quality = value.trim().parse::<f32>().ok().filter(|quality| (0.0..=1.0).contains(quality))?;
+            }
+        }
+        Some((media_type, quality))
+    })
+}
+
+// Tells whether a wildcard accepts JSON, which the header did not exclude with quality 0
+fn accepts_json_by_wildcard(accept: &str) -> bool {
+    let mut accepted = false;
+    for (media_type, quality) in media_ranges(accept) {
+        match media_type.as_str() {
+            "*/*" | "application/*" => accepted |= quality > 0.0,
+            "application/json" if quality <= 0.0 => return false,
+            _ => {}
+        }
+    }
+    accepted
+}
+
+///
+/// Rejection of requests whose ``Accept`` header names neither a supported media type nor a wildcard.
+///
+#[derive(Debug)]
+pub struct NotAcceptable(String);
+
+impl Reject for NotAcceptable {}
+
+impl fmt::Display for NotAcceptable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "None of the media types '{}' is supported, use {}, {}, or {}", self.0,
+            Encoding::Json.media_type(), Encoding::Cbor.media_type(), Encoding::MessagePack.media_type())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::domain::person_id::PersonId;
+    use crate::domain::person_event::PersonEvent;
+    use crate::domain::person_patch::PersonPatch;
+    use crate::rest::encoding::Encoding;
+    use crate::util::patch::Patch;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Encoding::negotiate("application/json"), Some(Encoding::Json));
+        assert_eq!(Encoding::negotiate("text/html, application/CBOR;q=0.9"), Some(Encoding::Cbor));
+        assert_eq!(Encoding::negotiate("application/x-msgpack"), Some(Encoding::MessagePack));
+        assert_eq!(Encoding::negotiate("application/msgpack, application/json"), Some(Encoding::MessagePack));
+        assert_eq!(Encoding::negotiate("text/event-stream"), None);
+        assert_eq!(Encoding::negotiate("*/*"), None);
+    }
+
+    #[test]
+    fn test_negotiate_quality() {
+        assert_eq!(Encoding::negotiate("application/json;q=0.5, application/cbor"), Some(Encoding::Cbor));
+        assert_eq!(Encoding::negotiate("application/msgpack; q=0.9, application/json; q=0.9"), Some(Encoding::MessagePack));
+        assert_eq!(Encoding::negotiate("application/cbor;q=0, application/json;q=0.1"), Some(Encoding::Json));
+        assert_eq!(Encoding::negotiate("application/cbor;q=0"), None);
+        assert_eq!(Encoding::negotiate("application/cbor;q=high, application/json;q=0.2"), Some(Encoding::Json));
+    }
+
+    #[test]
+    fn test_negotiate_against() {
+        let stream = "text/event-stream";
+        assert_eq!(Encoding::negotiate_against("application/json", stream), Some(Encoding::Json));
+        assert_eq!(Encoding::negotiate_against("text/event-stream, application/json;q=0.5", stream), None);
+        assert_eq!(Encoding::negotiate_against("text/event-stream;q=0.5, application/cbor", stream), Some(Encoding::Cbor));
+        assert_eq!(Encoding::negotiate_against("text/event-stream", stream), None);
+    }
+
+    #[test]
+    fn test_select() {
+        assert_eq!(Encoding::select(None).unwrap(), Encoding::Json);
+        assert_eq!(Encoding::select(Some("application/cbor")).unwrap(), Encoding::Cbor);
+        assert_eq!(Encoding::select(Some("*/*")).unwrap(), Encoding::Json);
+        assert_eq!(Encoding::select(Some("text/html, application/*;q=0.8")).unwrap(), Encoding::Json);
+        assert!(Encoding::select(Some("text/html, text/event-stream")).is_err());
+        assert!(Encoding::select(Some("application/json;q=0, */*")).is_err());
+    }
+
+    #[test]
+    fn test_cbor_null_and_absent() {
+        let event = person_event();
+        let bytes = Encoding::Cbor.encode(&event);
+        let value: ciborium::value::Value = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+        let patch = value.as_map().unwrap()[0].1.as_map().unwrap();
+        assert_eq!(patch.len(), 1); // Absent fields are skipped
+        assert_eq!(patch[0].0.as_text(), Some("city"));
+        assert!(patch[0].1.is_null());
+        assert_eq!(ciborium::de::from_reader::<PersonEvent, _>(bytes.as_slice()).unwrap(), event);
+    }
+
+    #[test]
+    fn test_msgpack_null_and_absent() {
+        let event = person_event();
+        let bytes = Encoding::MessagePack.encode(&event);
+        let value: BTreeMap<u64, BTreeMap<String, Option<String>>> = rmp_serde::from_slice(bytes.as_slice()).unwrap();
+        assert_eq!(value[&1].len(), 1); // Absent fields are skipped
+        assert_eq!(value[&1]["city"], None);
+        assert_eq!(rmp_serde::from_slice::<PersonEvent>(bytes.as_slice()).unwrap(), event);
+    }
+
+    fn person_event() -> PersonEvent {
+        PersonEvent::for_update(PersonId::from(1), &PersonPatch::new(None, Patch::Null, Patch::Absent))
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::{Arc, Mutex};
+use log::{debug, error, info};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use crate::aggregator::aggregator_facade::MutexAggregator;
+use crate::domain::event_type::EventType;
+use crate::error::Result;
+use crate::rest::event_fetcher::EVENT_PAGE_SIZE;
+use crate::util::ring_buffer::RingBuffer;
+
+///
+/// Shares the events of one [EventType](crate::domain::event_type::EventType) between all
+/// subscribers. A single reader task (see [spawn_event_broadcaster](spawn_event_broadcaster))
+/// reads new events from the database once per commit and pushes them into a
+/// [RingBuffer](crate::util::ring_buffer::RingBuffer). Subscribers are woken up through
+/// a ``watch`` channel that carries the latest buffered revision.
+///
+/// Subscribers read the database only while they catch up from revisions older than the buffer,
+/// see [EventFetcher](crate::rest::event_fetcher::EventFetcher).
+///
+pub struct EventBroadcaster {
+    buffer: Mutex<RingBuffer<String>>,
+    sender: watch::Sender<usize>
+}
+
+pub type ArcBroadcaster = Arc<EventBroadcaster>;
+
+impl EventBroadcaster {
+    fn new(capacity: usize, revision: usize) -> Self {
+        let (sender, _) = watch::channel(revision);
+        Self {
+            buffer: Mutex::new(RingBuffer::new(capacity, revision + 1)),
+            sender
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<usize> {
+        self.sender.subscribe()
+    }
+
+    /// Returns the buffered events from ``from_revision`` on or ``None`` if the caller must read the database.
+    pub fn read_from(&self, from_revision: usize) -> Option<Vec<(usize, String)>> {
+        self.buffer.lock().unwrap().read_from(from_revision)
+    }
+
+    fn next_revision(&self) -> usize {
+        self.buffer.lock().unwrap().next_revision()
+    }
+
+    fn publish(&self, events: Vec<(usize, String)>) {
+        if let Some((revision, _)) = events.last() {
+            let revision = *revision;
+            let mut buffer = self.buffer.lock().unwrap();
+            for (revision, event) in events {
+                buffer.push(revision, event);
+            }
+            self.sender.send_replace(revision);
+        }
+    }
+}
+
+// Reads all new events page by page
+fn read_pages(aggregator: &MutexAggregator, event_type: EventType, broadcaster: &EventBroadcaster) -> Result<()> {
+    loop {
+        let from_revision = broadcaster.next_revision();
+        let events = aggregator.lock().unwrap().get_events(event_type, from_revision, EVENT_PAGE_SIZE)?;
+        let full_page = events.len() == EVENT_PAGE_SIZE;
+        broadcaster.publish(events);
+        if !full_page {
+            return Ok(())
+        }
+    }
+}
+
+// Reads new events after every commit until the aggregator is dropped or a database error occurs
+async fn read_events(aggregator: MutexAggregator, event_type: EventType, broadcaster: ArcBroadcaster, mut revisions: watch::Receiver<usize>) {
+    while revisions.changed().await.is_ok() {
+        if let Err(err) = read_pages(&aggregator, event_type, &broadcaster) {
+            error!("Reading {:?} events failed: {:?}, leave event broadcaster", event_type, err);
+            break;
+        }
+    }
+    debug!("Leave {:?} event broadcaster", event_type);
+}
+
+///
+/// Creates an [EventBroadcaster](EventBroadcaster) that buffers up to ``capacity`` events,
+/// starting after the current revision of ``event_type``, and spawns its reader task.
+///
+pub fn spawn_event_broadcaster(aggregator: &MutexAggregator, event_type: EventType, capacity: usize) -> (ArcBroadcaster, JoinHandle<()>) {
+    info!("Spawn {:?} event broadcaster", event_type);
+    let mut revisions = aggregator.lock().unwrap().subscribe(event_type);
+    let revision = *revisions.borrow_and_update();
+    let broadcaster = Arc::new(EventBroadcaster::new(capacity, revision));
+    let handle = tokio::spawn(read_events(aggregator.clone(), event_type, broadcaster.clone(), revisions));
+    (broadcaster, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::time::timeout;
+    use crate::aggregator::aggregator_facade::{AggregatorFacade, MutexAggregator};
+    use crate::domain::event_type::EventType;
+    use crate::domain::person_data::PersonData;
+    use crate::rest::event_broadcaster::spawn_event_broadcaster;
+
+    #[tokio::test]
+    async fn test_broadcast() {
+        let aggregator: MutexAggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let person = PersonData::new("Ann", None, None);
+        assert!(aggregator.lock().unwrap().insert(&person).is_ok()); // Before spawn, so not buffered
+
+        let (broadcaster, handle) = spawn_event_broadcaster(&aggregator, EventType::PERSON, 10);
+        let mut revisions = broadcaster.subscribe();
+        assert_eq!(broadcaster.read_from(1), None); // Must be read from database
+        assert_eq!(broadcaster.read_from(2), Some(vec![]));
+
+        assert!(aggregator.lock().unwrap().insert(&person).is_ok());
+        assert!(timeout(Duration::from_secs(1), revisions.changed()).await.is_ok());
+        assert_eq!(*revisions.borrow(), 2);
+        assert_eq!(broadcaster.read_from(2), Some(vec![(2, String::from(r#"{"2":{"name":"Ann"}}"#))]));
+        handle.abort();
+    }
+}
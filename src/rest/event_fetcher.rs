@@ -1,38 +1,96 @@
-use crate::aggregator::aggregator_facade::MutexAggregator;
+use crate::aggregator::aggregator_facade::{CommitEvents, MutexAggregator};
 use crate::domain::event_type::EventType;
+use crate::error::DomainError;
+use crate::rest::event_broadcaster::ArcBroadcaster;
 use crate::util::scheduled_stream::Fetcher;
 
+/// Maximum number of events (or commits) read from the database at once.
+pub const EVENT_PAGE_SIZE: usize = 100;
+
 ///
-/// Implementation of trait [Fetcher](Fetcher) for serialized events retrieved from either
+/// Implementation of trait [Fetcher](Fetcher) for revisions and serialized events retrieved from either
 /// [PersonEventTable](crate::database::event_table::PersonEventTable) or
 /// [LocationEventTable](crate::database::event_table::LocationEventTable) trough
 /// [AggregatorFacade](crate::aggregator::aggregator_facade::AggregatorFacade).
 ///
+/// The database is only accessed while the fetcher catches up with events that are not (or no
+/// longer) buffered by the [EventBroadcaster](crate::rest::event_broadcaster::EventBroadcaster).
+/// Afterwards, all events are taken from the broadcaster. Database reads are done in pages of
+/// [EVENT_PAGE_SIZE](EVENT_PAGE_SIZE) events, so that the aggregator is locked only briefly.
+///
 /// Class ``EventFetcher`` is used by
 /// [ScheduledStream](crate::util::scheduled_stream::ScheduledStream) instantiated in function
 /// [get_events](crate::rest::rest_handlers::get_events).
 ///
 pub struct EventFetcher {
     aggregator: MutexAggregator,
+    broadcaster: ArcBroadcaster,
     event_type: EventType,
-    offset: usize
+    offset: usize,
+    more: bool // Whether the last page read from database was full
 }
 
 impl EventFetcher {
-    pub fn new(aggregator: MutexAggregator, event_type: EventType, offset: usize) -> Self {
-        Self { aggregator, event_type, offset }
+    pub fn new(aggregator: MutexAggregator, broadcaster: ArcBroadcaster, event_type: EventType, offset: usize) -> Self {
+        Self { aggregator, broadcaster, event_type, offset, more: false }
     }
 }
 
-impl Fetcher<String, rusqlite::Error> for EventFetcher {
-    fn fetch(&mut self) -> Result<Vec<String>, rusqlite::Error> {
-        let mut aggregator = self.aggregator.lock().unwrap();
-        return match aggregator.get_events(self.event_type, self.offset) {
-            Err(err) => Err(err),
-            Ok(events) => {
-                self.offset += events.len();
-                Ok(events)
+impl Fetcher<(usize, String), DomainError> for EventFetcher {
+    fn fetch(&mut self) -> Result<Vec<(usize, String)>, DomainError> {
+        let events = match self.broadcaster.read_from(self.offset) {
+            Some(events) => {
+                self.more = false;
+                events
+            },
+            None => {
+                let mut aggregator = self.aggregator.lock().unwrap();
+                let events = aggregator.get_events(self.event_type, self.offset, EVENT_PAGE_SIZE)?;
+                self.more = events.len() == EVENT_PAGE_SIZE;
+                events
             }
+        };
+        // Continue after the last delivered revision (revisions may have gaps)
+        if let Some((revision, _)) = events.last() {
+            self.offset = revision + 1;
         }
+        Ok(events)
     }
-}
\ No newline at end of file
+
+    fn has_more(&self) -> bool {
+        self.more
+    }
+}
+
+///
+/// Implementation of trait [Fetcher](Fetcher) for the events of both types grouped by commit,
+/// retrieved through [AggregatorFacade](crate::aggregator::aggregator_facade::AggregatorFacade)
+/// in pages of [EVENT_PAGE_SIZE](EVENT_PAGE_SIZE) commits. Used by function
+/// [get_commit_events](crate::rest::rest_handlers::get_commit_events).
+///
+pub struct CommitFetcher {
+    aggregator: MutexAggregator,
+    offset: usize,
+    more: bool // Whether the last page read from database was full
+}
+
+impl CommitFetcher {
+    pub fn new(aggregator: MutexAggregator, offset: usize) -> Self {
+        Self { aggregator, offset, more: false }
+    }
+}
+
+impl Fetcher<CommitEvents, DomainError> for CommitFetcher {
+    fn fetch(&mut self) -> Result<Vec<CommitEvents>, DomainError> {
+        let commits = self.aggregator.lock().unwrap().get_commits(self.offset, EVENT_PAGE_SIZE)?;
+        self.more = commits.len() == EVENT_PAGE_SIZE;
+        if let Some((commit_id, _)) = commits.last() {
+            self.offset = commit_id + 1;
+        }
+        Ok(commits)
+    }
+
+    fn has_more(&self) -> bool {
+        self.more
+    }
+}
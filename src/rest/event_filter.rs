@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use serde_json::{Map, Value};
+use crate::domain::person_id::PersonId;
+
+///
+/// Filters serialized events by their top-level keys, i.e. the person ids of
+/// [PersonEvent](crate::domain::person_event::PersonEvent)s or the cities of
+/// [LocationEvent](crate::domain::location_event::LocationEvent)s.
+///
+/// Events are only dropped, never renumbered, so the revisions of the remaining events
+/// stay valid for resuming a stream.
+///
+#[derive(Debug, Eq, PartialEq)]
+pub struct EventFilter {
+    keys: HashSet<String>
+}
+
+impl EventFilter {
+    /// Creates a filter from a comma-separated list of person ids, for example ``1,7,42``.
+    pub fn for_persons(ids: &str) -> Result<Self, ParseIntError> {
+        let keys = ids.split(',')
+            .map(|id| PersonId::from_str(id.trim()).map(|id| id.to_string()))
+            .collect::<Result<HashSet<String>, ParseIntError>>()?;
+        Ok(Self { keys })
+    }
+
+    /// Creates a filter from a comma-separated list of cities, for example ``Rome,Paris``.
+    pub fn for_locations(cities: &str) -> Self {
+        let keys = cities.split(',').map(|city| city.trim().to_string()).collect();
+        Self { keys }
+    }
+
+    /// Returns ``event`` reduced to the matching keys, or ``None`` if no key matches.
+    pub fn apply(&self, event: String) -> Option<String> {
+        // Events are serialized by the aggregators, errors should not happen, panic accepted
+        let mut map: Map<String, Value> = serde_json::from_str(&event).unwrap();
+        let count = map.len();
+        map.retain(|key, _| self.keys.contains(key));
+        if map.is_empty() {
+            None
+        } else if map.len() == count {
+            Some(event) // Avoid serializing again
+        } else {
+            Some(serde_json::to_string(&map).unwrap())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rest::event_filter::EventFilter;
+
+    #[test]
+    fn test_for_persons() {
+        let filter = EventFilter::for_persons("1, 07,42");
+        assert!(filter.is_ok());
+        let filter = filter.unwrap();
+        assert_eq!(filter.apply(String::from(r#"{"7":{"name":"Ann"}}"#)), Some(String::from(r#"{"7":{"name":"Ann"}}"#)));
+        assert_eq!(filter.apply(String::from(r#"{"42":null}"#)), Some(String::from(r#"{"42":null}"#)));
+        assert_eq!(filter.apply(String::from(r#"{"2":{"name":"Bob"}}"#)), None);
+    }
+
+    #[test]
+    fn test_for_persons_invalid() {
+        assert!(EventFilter::for_persons("1,x").is_err());
+        assert!(EventFilter::for_persons("").is_err());
+    }
+
+    #[test]
+    fn test_for_locations() {
+        let filter = EventFilter::for_locations("Rome,Paris");
+        assert_eq!(filter.apply(String::from(r#"{"Rome":{"total":1}}"#)), Some(String::from(r#"{"Rome":{"total":1}}"#)));
+        assert_eq!(filter.apply(String::from(r#"{"Berlin":null}"#)), None);
+    }
+
+    #[test]
+    fn test_apply_partial() {
+        let filter = EventFilter::for_locations("Rome");
+        assert_eq!(filter.apply(String::from(r#"{"Paris":null,"Rome":{"total":2}}"#)), Some(String::from(r#"{"Rome":{"total":2}}"#)));
+    }
+}
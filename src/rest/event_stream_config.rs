@@ -0,0 +1,86 @@
+use std::env;
+use std::time::Duration;
+
+/// Environment variable with the interval of keep-alive comments in seconds
+pub const KEEP_ALIVE_VARIABLE: &str = "EVENT_STREAM_KEEP_ALIVE";
+/// Environment variable with the reconnection delay proposed to SSE clients in seconds
+pub const RETRY_VARIABLE: &str = "EVENT_STREAM_RETRY";
+
+///
+/// Timing parameters of the event streams.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EventStreamConfig {
+    /// Fallback interval for polling new events, because streams are woken up after every commit
+    pub repeat_every: Duration,
+    /// Interval of keep-alive comments sent on idle streams, to keep proxies from closing them
+    pub keep_alive: Duration,
+    /// Reconnection delay proposed to SSE clients in the ``retry`` field
+    pub retry: Duration
+}
+
+impl Default for EventStreamConfig {
+    fn default() -> Self {
+        Self {
+            repeat_every: Duration::from_secs(60),
+            keep_alive: Duration::from_secs(15),
+            retry: Duration::from_secs(3)
+        }
+    }
+}
+
+impl EventStreamConfig {
+    ///
+    /// Creates the default configuration, with the keep-alive interval and the retry delay
+    /// overridden by environment variables [KEEP_ALIVE_VARIABLE] and [RETRY_VARIABLE] if they are set.
+    ///
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_lookup(|name| env::var(name).ok())
+    }
+
+    // Separated from from_env(), so that tests do not have to change the environment of the process
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let mut config = Self::default();
+        if let Some(value) = lookup(KEEP_ALIVE_VARIABLE) {
+            config.keep_alive = parse_seconds(KEEP_ALIVE_VARIABLE, &value)?;
+        }
+        if let Some(value) = lookup(RETRY_VARIABLE) {
+            config.retry = parse_seconds(RETRY_VARIABLE, &value)?;
+        }
+        Ok(config)
+    }
+}
+
+// A zero interval would make the keep-alive timer fire continuously
+fn parse_seconds(name: &str, value: &str) -> Result<Duration, String> {
+    match value.trim().parse::<u64>() {
+        Ok(seconds) if seconds > 0 => Ok(Duration::from_secs(seconds)),
+        _ => Err(format!("{} must be a positive number of seconds, not '{}'", name, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::rest::event_stream_config::{EventStreamConfig, KEEP_ALIVE_VARIABLE, RETRY_VARIABLE};
+
+    #[test]
+    fn test_from_lookup() {
+        let config = EventStreamConfig::from_lookup(|_| None).unwrap();
+        assert_eq!(config, EventStreamConfig::default());
+        let config = EventStreamConfig::from_lookup(|name| match name {
+            KEEP_ALIVE_VARIABLE => Some(String::from("30")),
+            RETRY_VARIABLE => Some(String::from(" 5 ")),
+            _ => None
+        }).unwrap();
+        assert_eq!(config.keep_alive, Duration::from_secs(30));
+        assert_eq!(config.retry, Duration::from_secs(5));
+        assert_eq!(config.repeat_every, Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_from_lookup_invalid() {
+        assert!(EventStreamConfig::from_lookup(|name| (name == RETRY_VARIABLE).then(|| String::from("soon"))).is_err());
+        assert!(EventStreamConfig::from_lookup(|name| (name == KEEP_ALIVE_VARIABLE).then(|| String::from("0"))).is_err());
+    }
+}
@@ -1,50 +1,173 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
 use log::{debug, info};
 use tokio::sync::broadcast::Receiver;
 use tokio::task::JoinHandle;
-use warp::Filter;
+use warp::{Filter, Reply};
 use crate::aggregator::aggregator_facade::MutexAggregator;
 use crate::domain::event_type::EventType;
 use crate::domain::person_id::PersonId;
-use crate::rest::rest_handlers::{post_person, patch_person, delete_person, get_persons, get_events, get_locations};
+use crate::rest::encoding::Encoding;
+use crate::rest::event_broadcaster::{ArcBroadcaster, spawn_event_broadcaster};
+use crate::rest::event_stream_config::EventStreamConfig;
+use crate::rest::rest_handlers::{post_person, post_batch, put_person, patch_person, delete_person, get_persons, get_person, get_location, get_events, get_locations, get_event_page, get_commit_events, follow_aggregates, reject_invalid_query, reject_malformed_request, CommitQuery, LocationQuery, PersonQuery, EventQuery, EventPageQuery};
+use crate::rest::socket_handler::handle_socket;
 
 const REVISION_HEADER: &'static str = "X-Revision";
+const COMMIT_HEADER: &str = "X-Commit";
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+const IF_NONE_MATCH_HEADER: &str = "If-None-Match";
+const IF_MATCH_HEADER: &str = "If-Match";
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const EVENT_STREAM_MEDIA_TYPE: &str = "text/event-stream";
+const EVENT_BUFFER_SIZE: usize = 1000; // Number of recent events kept in memory per event type
+const MAX_BATCH_BODY_SIZE: u64 = 1024 * 1024; // Bytes, enough for the maximum number of operations per batch
 
 fn with_aggregator(aggregator: MutexAggregator)
     -> impl Filter<Extract = (MutexAggregator,), Error = Infallible> + Clone {
     warp::any().map(move || aggregator.clone())
 }
 
+fn with_broadcaster(broadcaster: ArcBroadcaster)
+    -> impl Filter<Extract = (ArcBroadcaster,), Error = Infallible> + Clone {
+    warp::any().map(move || broadcaster.clone())
+}
+
+// Passes requests that prefer a supported encoding over text/event-stream, so that they are not answered with an event stream
+fn accepts_encoding() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("accept")
+        .and_then(|accept: Option<String>| async move {
+            match accept.as_deref().and_then(|accept| Encoding::negotiate_against(accept, EVENT_STREAM_MEDIA_TYPE)) {
+                Some(_) => Ok(()),
+                None => Err(warp::reject::not_found())
+            }
+        })
+        .untuple_one()
+}
+
+// Extracts the encoding negotiated by header Accept, JSON if there is none or a wildcard
+fn with_encoding() -> impl Filter<Extract = (Encoding,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("accept")
+        .and_then(|accept: Option<String>| async move {
+            Encoding::select(accept.as_deref()).map_err(warp::reject::custom)
+        })
+}
+
+// Passes requests with query parameter follow=true, which ask for the aggregates followed by their change events.
+// Extracts the names of all other query parameters, which cannot be combined with follow=true.
+fn follows() -> impl Filter<Extract = (Vec<String>,), Error = warp::Rejection> + Clone {
+    warp::query::<HashMap<String, String>>()
+        .and_then(|query: HashMap<String, String>| async move {
+            match query.get("follow") {
+                Some(follow) if follow == "true" => {
+                    let mut parameters: Vec<String> = query.into_keys().filter(|name| name != "follow").collect();
+                    parameters.sort();
+                    Ok(parameters)
+                },
+                _ => Err(warp::reject::not_found())
+            }
+        })
+}
+
 // Allows to pass any constant to a Warp filter
 fn with_constant<T:Send+Copy>(argument: T) -> impl Filter<Extract = (T,), Error = Infallible> + Clone {
     warp::any().map(move || argument)
 }
 
-pub fn spawn_http_server(aggregator: &MutexAggregator, mut rx: Receiver<()>, repeat_every_secs: u64) -> JoinHandle<()> {
+// Answers requests for a page of events of event_type, which are tried before the event stream of the same path
+fn event_page_route(path: &'static str, aggregator: MutexAggregator, event_type: EventType)
+    -> impl Filter<Extract = (impl Reply,), Error = warp::Rejection> + Clone {
+    warp::path(path)
+        .and(warp::get())
+        .and(accepts_encoding())
+        .and(with_aggregator(aggregator)
+            .and(with_constant(event_type))
+            .and(warp::query::<EventPageQuery>())
+            .and(with_encoding())
+            .and_then(get_event_page)
+            .recover(reject_invalid_query)) // Otherwise the event stream route would answer
+}
+
+pub fn spawn_http_server(aggregator: &MutexAggregator, mut rx: Receiver<()>, config: EventStreamConfig) -> JoinHandle<()> {
     info!("Spawn HTTP server");
 
     let path_persons = "persons";
+    let path_batch = "batch";
     let path_person_events = "person-events";
     let path_locations = "locations";
     let path_location_events = "location-events";
+    let path_event_socket = "event-socket";
+    let path_events = "events";
+
+    let (person_broadcaster, person_reader) = spawn_event_broadcaster(aggregator, EventType::PERSON, EVENT_BUFFER_SIZE);
+    let (location_broadcaster, location_reader) = spawn_event_broadcaster(aggregator, EventType::LOCATION, EVENT_BUFFER_SIZE);
+
+    let route_follow_persons = warp::path(path_persons)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_aggregator(aggregator.clone()))
+        .and(with_broadcaster(person_broadcaster.clone()))
+        .and(with_constant(EventType::PERSON))
+        .and(with_constant(config))
+        .and(follows())
+        .and(warp::header::optional::<usize>(LAST_EVENT_ID_HEADER))
+        .and_then(follow_aggregates);
 
     let route_get_persons = warp::path(path_persons)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_aggregator(aggregator.clone())
+            .and(with_constant(path_persons))
+            .and(warp::query::<PersonQuery>())
+            .and(with_constant(REVISION_HEADER))
+            .and(with_constant(COMMIT_HEADER))
+            .and(warp::header::optional::<String>(IF_NONE_MATCH_HEADER))
+            .and(with_encoding())
+            .and_then(get_persons)
+            .recover(reject_invalid_query)); // Otherwise the other person routes would answer
+
+    let route_get_person = warp::path(path_persons)
         .and(warp::get())
         .and(with_aggregator(aggregator.clone()))
+        .and(warp::path::param::<PersonId>())
+        .and(warp::path::end())
         .and(with_constant(REVISION_HEADER))
-        .and_then(get_persons);
+        .and(with_constant(COMMIT_HEADER))
+        .and(warp::header::optional::<String>(IF_NONE_MATCH_HEADER))
+        .and(with_encoding())
+        .and_then(get_person);
+
+    let route_post_batch = warp::path(path_persons)
+        .and(warp::path(path_batch))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_aggregator(aggregator.clone()))
+        .and(warp::body::content_length_limit(MAX_BATCH_BODY_SIZE))
+        .and(warp::body::json())
+        .and_then(post_batch);
 
     let route_post_person = warp::path(path_persons)
         .and(warp::post())
         .and(with_aggregator(aggregator.clone()))
         .and(with_constant(path_persons))
+        .and(warp::header::optional::<String>(IDEMPOTENCY_KEY_HEADER))
         .and(warp::body::json())
         .and_then(post_person);
 
+    let route_put_person = warp::path(path_persons)
+        .and(warp::put())
+        .and(with_aggregator(aggregator.clone()))
+        .and(with_constant(path_persons))
+        .and(warp::path::param::<PersonId>())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
+        .and(warp::body::json())
+        .and_then(put_person);
+
     let route_patch_person = warp::path(path_persons)
         .and(warp::patch())
         .and(with_aggregator(aggregator.clone()))
         .and(warp::path::param::<PersonId>())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
         .and(warp::body::json())
         .and_then(patch_person);
 
@@ -52,37 +175,106 @@ pub fn spawn_http_server(aggregator: &MutexAggregator, mut rx: Receiver<()>, rep
         .and(warp::delete())
         .and(with_aggregator(aggregator.clone()))
         .and(warp::path::param::<PersonId>())
+        .and(warp::header::optional::<String>(IF_MATCH_HEADER))
         .and_then(delete_person);
 
+    let route_get_person_event_page = event_page_route(path_person_events, aggregator.clone(), EventType::PERSON);
+
     let route_get_person_events = warp::path(path_person_events)
         .and(warp::get())
         .and(with_aggregator(aggregator.clone()))
+        .and(with_broadcaster(person_broadcaster.clone()))
         .and(with_constant(EventType::PERSON))
-        .and(with_constant(repeat_every_secs))
+        .and(with_constant(config))
+        .and(warp::query::<EventQuery>())
         .and(warp::header::optional::<usize>(REVISION_HEADER))
+        .and(warp::header::optional::<usize>(LAST_EVENT_ID_HEADER))
         .and_then(get_events);
 
+    let route_follow_locations = warp::path(path_locations)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_aggregator(aggregator.clone()))
+        .and(with_broadcaster(location_broadcaster.clone()))
+        .and(with_constant(EventType::LOCATION))
+        .and(with_constant(config))
+        .and(follows())
+        .and(warp::header::optional::<usize>(LAST_EVENT_ID_HEADER))
+        .and_then(follow_aggregates);
+
     let route_get_locations = warp::path(path_locations)
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_aggregator(aggregator.clone())
+            .and(warp::query::<LocationQuery>())
+            .and(with_constant(REVISION_HEADER))
+            .and(with_constant(COMMIT_HEADER))
+            .and(warp::header::optional::<String>(IF_NONE_MATCH_HEADER))
+            .and(with_encoding())
+            .and_then(get_locations)
+            .recover(reject_invalid_query)); // Otherwise the location item route would answer
+
+    let route_get_location = warp::path(path_locations)
         .and(warp::get())
         .and(with_aggregator(aggregator.clone()))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
         .and(with_constant(REVISION_HEADER))
-        .and_then(get_locations);
+        .and(with_constant(COMMIT_HEADER))
+        .and(warp::header::optional::<String>(IF_NONE_MATCH_HEADER))
+        .and(with_encoding())
+        .and_then(get_location);
+
+    let route_get_location_event_page = event_page_route(path_location_events, aggregator.clone(), EventType::LOCATION);
 
     let route_get_location_events = warp::path(path_location_events)
         .and(warp::get())
         .and(with_aggregator(aggregator.clone()))
+        .and(with_broadcaster(location_broadcaster.clone()))
         .and(with_constant(EventType::LOCATION))
-        .and(with_constant(repeat_every_secs))
+        .and(with_constant(config))
+        .and(warp::query::<EventQuery>())
         .and(warp::header::optional::<usize>(REVISION_HEADER))
+        .and(warp::header::optional::<usize>(LAST_EVENT_ID_HEADER))
         .and_then(get_events);
 
-    let routes = route_get_persons
+    let route_get_commit_events = warp::path(path_events)
+        .and(warp::get())
+        .and(with_aggregator(aggregator.clone()))
+        .and(with_constant(config))
+        .and(warp::query::<CommitQuery>())
+        .and(warp::header::optional::<usize>(COMMIT_HEADER))
+        .and(warp::header::optional::<usize>(LAST_EVENT_ID_HEADER))
+        .and_then(get_commit_events);
+
+    let route_event_socket = warp::path(path_event_socket)
+        .and(warp::ws())
+        .and(with_aggregator(aggregator.clone()))
+        .and(with_broadcaster(person_broadcaster))
+        .and(with_broadcaster(location_broadcaster))
+        .and(with_constant(config))
+        .map(|ws: warp::ws::Ws, aggregator, person_broadcaster, location_broadcaster, config| {
+            ws.on_upgrade(move |socket| handle_socket(socket, aggregator, person_broadcaster, location_broadcaster, config))
+        });
+
+    let routes = route_follow_persons
+        .or(route_get_persons)
+        .or(route_get_person)
+        .or(route_post_batch)
         .or(route_post_person)
+        .or(route_put_person)
         .or(route_patch_person)
         .or(route_delete_person)
+        .or(route_get_person_event_page)
         .or(route_get_person_events)
+        .or(route_follow_locations)
         .or(route_get_locations)
-        .or(route_get_location_events);
+        .or(route_get_location)
+        .or(route_get_location_event_page)
+        .or(route_get_location_events)
+        .or(route_get_commit_events)
+        .or(route_event_socket)
+        .recover(reject_malformed_request);
 
     let (_, server) = warp::serve(routes)
         .bind_with_graceful_shutdown(([127, 0, 0, 1], 3000), async move {
@@ -90,5 +282,80 @@ pub fn spawn_http_server(aggregator: &MutexAggregator, mut rx: Receiver<()>, rep
             debug!("Termination signal received, leave HTTP server");
         });
 
-    tokio::spawn(server)
-}
\ No newline at end of file
+    tokio::spawn(async move {
+        server.await;
+        person_reader.abort();
+        location_reader.abort();
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use warp::{Filter, Reply};
+    use warp::http::StatusCode;
+    use crate::aggregator::aggregator_facade::AggregatorFacade;
+    use crate::domain::event_type::EventType;
+    use crate::domain::person_data::PersonData;
+    use crate::rest::encoding::Encoding;
+    use crate::rest::http_server::{accepts_encoding, event_page_route, follows, with_encoding};
+    use crate::rest::problem::PROBLEM_MEDIA_TYPE;
+    use crate::rest::rest_handlers::reject_malformed_request;
+
+    #[tokio::test]
+    async fn test_follows() {
+        let parameters = warp::test::request().path("/persons?follow=true").filter(&follows()).await;
+        assert_eq!(parameters.unwrap(), Vec::<String>::new());
+        // Other parameters are passed on, so that the handler can reject them
+        let parameters = warp::test::request().path("/persons?limit=2&city=Rome&follow=true").filter(&follows()).await;
+        assert_eq!(parameters.unwrap(), vec!["city", "limit"]);
+        assert!(!warp::test::request().path("/persons?follow=false").matches(&follows()).await);
+        assert!(!warp::test::request().path("/persons").matches(&follows()).await);
+    }
+
+    #[tokio::test]
+    async fn test_accepts_encoding() {
+        assert!(warp::test::request().header("accept", "application/json").matches(&accepts_encoding()).await);
+        assert!(warp::test::request().header("accept", "application/cbor").matches(&accepts_encoding()).await);
+        assert!(!warp::test::request().header("accept", "text/event-stream").matches(&accepts_encoding()).await);
+        assert!(!warp::test::request().header("accept", "application/json;q=0").matches(&accepts_encoding()).await);
+        assert!(!warp::test::request().header("accept", "text/event-stream, application/json;q=0.9").matches(&accepts_encoding()).await);
+        assert!(!warp::test::request().matches(&accepts_encoding()).await);
+    }
+
+    #[tokio::test]
+    async fn test_with_encoding() {
+        let encoding = warp::test::request().header("accept", "application/msgpack").filter(&with_encoding()).await;
+        assert_eq!(encoding.unwrap(), Encoding::MessagePack);
+        let encoding = warp::test::request().filter(&with_encoding()).await;
+        assert_eq!(encoding.unwrap(), Encoding::Json);
+        let encoding = warp::test::request().header("accept", "*/*").filter(&with_encoding()).await;
+        assert_eq!(encoding.unwrap(), Encoding::Json);
+    }
+
+    #[tokio::test]
+    async fn test_with_encoding_not_acceptable() {
+        let filter = with_encoding()
+            .map(|encoding: Encoding| Box::new(encoding.reply(&"Ann")) as Box<dyn Reply>)
+            .recover(reject_malformed_request);
+        let response = warp::test::request().header("accept", "text/html").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_event_page_route_limit() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        aggregator.lock().unwrap().insert(&PersonData::new("Ann", None, None)).unwrap();
+        aggregator.lock().unwrap().insert(&PersonData::new("Bob", None, None)).unwrap();
+        let route = event_page_route("person-events", aggregator, EventType::PERSON);
+        // An empty page would never advance, so at least one event is returned
+        let response = warp::test::request().path("/person-events?limit=0").header("accept", "application/json").reply(&route).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let page: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        assert_eq!(page["events"].as_array().unwrap().len(), 1);
+        assert_eq!(page["next_revision"], 2);
+        let response = warp::test::request().path("/person-events?limit=-1").header("accept", "application/json").reply(&route).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
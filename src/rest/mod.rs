@@ -1,3 +1,9 @@
 mod rest_handlers;
 pub mod http_server;
+pub mod event_stream_config;
 mod event_fetcher;
+mod event_broadcaster;
+mod event_filter;
+mod socket_handler;
+mod encoding;
+mod problem;
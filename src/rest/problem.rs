@@ -0,0 +1,194 @@
+use serde::Serialize;
+use warp::http::StatusCode;
+use warp::{reply, Reply};
+use crate::domain::field_error::FieldError;
+use crate::error::DomainError;
+
+/// Media type of problem details (RFC 7807)
+pub const PROBLEM_MEDIA_TYPE: &str = "application/problem+json";
+
+///
+/// The kinds of problems the REST handlers answer with. Clients should act upon the ``type``
+/// of a problem rather than its ``detail``, which is meant for humans.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ProblemType {
+    BadRequest,
+    NotFound,
+    NotAcceptable,
+    Gone,
+    Conflict,
+    PreconditionFailed,
+    PayloadTooLarge,
+    Validation,
+    Constraint,
+    Storage
+}
+
+impl ProblemType {
+    /// The URI reference of member ``type``, resolved against the URI of the request.
+    pub fn uri(&self) -> &'static str {
+        match self {
+            ProblemType::BadRequest => "/problems/bad-request",
+            ProblemType::NotFound => "/problems/not-found",
+            ProblemType::NotAcceptable => "/problems/not-acceptable",
+            ProblemType::Gone => "/problems/gone",
+            ProblemType::Conflict => "/problems/conflict",
+            ProblemType::PreconditionFailed => "/problems/precondition-failed",
+            ProblemType::PayloadTooLarge => "/problems/payload-too-large",
+            ProblemType::Validation => "/problems/validation",
+            ProblemType::Constraint => "/problems/constraint",
+            ProblemType::Storage => "/problems/storage"
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            ProblemType::BadRequest => "Invalid request parameters",
+            ProblemType::NotFound => "Resource not found",
+            ProblemType::NotAcceptable => "Media type not supported",
+            ProblemType::Gone => "Data expired",
+            ProblemType::Conflict => "Conflicting request",
+            ProblemType::PreconditionFailed => "Revision does not match",
+            ProblemType::PayloadTooLarge => "Request too large",
+            ProblemType::Validation => "Invalid person data",
+            ProblemType::Constraint => "Constraint violated",
+            ProblemType::Storage => "Storage failed"
+        }
+    }
+}
+
+///
+/// Problem details (RFC 7807), the body of all error responses of the REST handlers.
+/// Besides the standard members, a problem may carry the extension members ``revision``
+/// (the oldest revision still available, or the current revision of a person that did not match),
+/// ``operation`` (the index of the failed batch operation), and ``errors`` (one entry per invalid field).
+///
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    operation: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>
+}
+
+impl Problem {
+    pub fn new(problem_type: ProblemType, status: StatusCode, detail: &str) -> Self {
+        Self {
+            problem_type: problem_type.uri(),
+            title: problem_type.title(),
+            status: status.as_u16(),
+            detail: String::from(detail),
+            revision: None,
+            operation: None,
+            errors: Vec::new()
+        }
+    }
+
+    pub fn bad_request(detail: &str) -> Self {
+        Self::new(ProblemType::BadRequest, StatusCode::BAD_REQUEST, detail)
+    }
+
+    pub fn not_found(detail: &str) -> Self {
+        Self::new(ProblemType::NotFound, StatusCode::NOT_FOUND, detail)
+    }
+
+    pub fn not_acceptable(detail: &str) -> Self {
+        Self::new(ProblemType::NotAcceptable, StatusCode::NOT_ACCEPTABLE, detail)
+    }
+
+    pub fn payload_too_large(detail: &str) -> Self {
+        Self::new(ProblemType::PayloadTooLarge, StatusCode::PAYLOAD_TOO_LARGE, detail)
+    }
+
+    /// Tells the client the oldest ``revision`` it can continue with.
+    pub fn gone(detail: &str, revision: usize) -> Self {
+        Self { revision: Some(revision), ..Self::new(ProblemType::Gone, StatusCode::GONE, detail) }
+    }
+
+    ///
+    /// Maps a [DomainError](DomainError) to its problem. Constraint violations are conflicts with the stored data,
+    /// so they are answered with ``409`` like other conflicts. Malformed requests are answered with ``400``,
+    /// requests with invalid values with ``422``. Failed batch operations keep the status of their cause.
+    ///
+    pub fn of(error: &DomainError) -> Self {
+        let detail = error.to_string();
+        match error {
+            DomainError::NotFound(_) => Self::not_found(&detail),
+            DomainError::Conflict(_) => Self::new(ProblemType::Conflict, StatusCode::CONFLICT, &detail),
+            DomainError::PreconditionFailed(_, revision) => {
+                Self { revision: *revision, ..Self::new(ProblemType::PreconditionFailed, StatusCode::PRECONDITION_FAILED, &detail) }
+            },
+            DomainError::Validation(errors) => {
+                let status = if errors.iter().any(FieldError::is_malformed) { StatusCode::BAD_REQUEST } else { StatusCode::UNPROCESSABLE_ENTITY };
+                Self { errors: errors.clone(), ..Self::new(ProblemType::Validation, status, &detail) }
+            },
+            DomainError::Constraint(_) => Self::new(ProblemType::Constraint, StatusCode::CONFLICT, &detail),
+            DomainError::Storage(_) => Self::new(ProblemType::Storage, StatusCode::INTERNAL_SERVER_ERROR, &detail),
+            DomainError::Operation(index, cause) => Self { detail, operation: Some(*index), ..Self::of(cause) }
+        }
+    }
+
+    /// Replies with this problem, its status, and ``Content-Type: application/problem+json``.
+    pub fn reply(&self) -> Box<dyn Reply> {
+        // Problems are plain data, so the status is valid
+        let status = StatusCode::from_u16(self.status).unwrap();
+        let response = reply::with_header(reply::json(self), "Content-Type", PROBLEM_MEDIA_TYPE);
+        Box::new(reply::with_status(response, status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use warp::http::StatusCode;
+    use warp::Reply;
+    use crate::domain::field_error::{BLANK, EMPTY, FieldError};
+    use crate::error::DomainError;
+    use crate::rest::problem::{Problem, PROBLEM_MEDIA_TYPE};
+
+    #[test]
+    fn test_status_of_errors() {
+        let status = |error: DomainError| Problem::of(&error).status;
+        assert_eq!(status(DomainError::NotFound(String::from("Person 1 not found"))), 404);
+        assert_eq!(status(DomainError::Conflict(String::from("Key reused"))), 409);
+        assert_eq!(status(DomainError::PreconditionFailed(String::from("Person 1 was changed"), Some(3))), 412);
+        assert_eq!(status(DomainError::Validation(vec![FieldError::new(Some("name"), BLANK, "Name must not be blank")])), 422);
+        assert_eq!(status(DomainError::Validation(vec![FieldError::new(None, EMPTY, "Patch must change at least one field")])), 400);
+        assert_eq!(status(DomainError::Constraint(String::from("UNIQUE constraint failed"))), 409);
+        assert_eq!(status(DomainError::Storage(rusqlite::Error::InvalidQuery)), 500);
+        assert_eq!(status(DomainError::Operation(1, Box::new(DomainError::NotFound(String::from("Person 1 not found"))))), 404);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let problem = Problem::of(&DomainError::PreconditionFailed(String::from("Person 1 was changed, its revision is 3"), Some(3)));
+        let json = serde_json::to_string(&problem).unwrap();
+        assert_eq!(json, r#"{"type":"/problems/precondition-failed","title":"Revision does not match","status":412,"detail":"Person 1 was changed, its revision is 3","revision":3}"#);
+
+        let error = DomainError::Validation(vec![FieldError::new(Some("name"), BLANK, "Name must not be blank")]);
+        let problem = Problem::of(&DomainError::Operation(2, Box::new(error)));
+        let json = serde_json::to_string(&problem).unwrap();
+        assert_eq!(json, concat!(r#"{"type":"/problems/validation","title":"Invalid person data","status":422,"#,
+            r#""detail":"Operation 2 failed, no operation was applied: Invalid person data: Name must not be blank","#,
+            r#""operation":2,"errors":[{"field":"name","code":"blank","message":"Name must not be blank"}]}"#));
+
+        let json = serde_json::to_string(&Problem::gone("Snapshot expired", 5)).unwrap();
+        assert_eq!(json, r#"{"type":"/problems/gone","title":"Data expired","status":410,"detail":"Snapshot expired","revision":5}"#);
+    }
+
+    #[test]
+    fn test_reply() {
+        let response = Problem::bad_request("Invalid query").reply().into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+        let response = Problem::payload_too_large("Too many operations").reply().into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
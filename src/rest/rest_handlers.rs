@@ -1,107 +1,1037 @@
 use std::convert::Infallible;
-use std::time::Duration;
+use percent_encoding::percent_decode_str;
 use serde::{Serialize, Deserialize};
-use futures_util::StreamExt;
+use serde_json::value::RawValue;
+use futures_util::{future, stream, StreamExt};
+use tokio_stream::wrappers::WatchStream;
 use warp::http::StatusCode;
-use warp::{reply, Reply, sse};
+use warp::{reply, Rejection, Reply, sse};
+use warp::body::BodyDeserializeError;
+use warp::reject::{InvalidHeader, InvalidQuery, PayloadTooLarge};
 use warp::sse::Event;
-use crate::aggregator::aggregator_facade::MutexAggregator;
+use crate::aggregator::aggregator_facade::{BatchResult, CommitEvents, Idempotent, MutexAggregator, PointInTime};
+use crate::domain::person_filter::PersonFilter;
 use crate::domain::event_type::EventType;
+use crate::domain::location_event::LocationEvent;
 use crate::domain::person_data::PersonData;
+use crate::domain::person_event::PersonEvent;
 use crate::domain::person_id::PersonId;
+use crate::domain::person_operation::PersonOperation;
 use crate::domain::person_patch::PersonPatch;
-use crate::rest::event_fetcher::EventFetcher;
+use crate::domain::precondition::Precondition;
+use crate::error::{DomainError, Result as DomainResult};
+use crate::rest::encoding::{Encoding, NotAcceptable};
+use crate::rest::event_broadcaster::ArcBroadcaster;
+use crate::rest::event_filter::EventFilter;
+use crate::rest::event_fetcher::{CommitFetcher, EventFetcher, EVENT_PAGE_SIZE};
+use crate::rest::event_stream_config::EventStreamConfig;
+use crate::rest::problem::{Problem, ProblemType};
 use crate::util::scheduled_stream::ScheduledStream;
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
-struct ErrorResult {
-    error: String
+const MAX_EVENT_PAGE_SIZE: usize = 1000;
+const PERSON_PAGE_SIZE: usize = 100;
+const MAX_PERSON_PAGE_SIZE: usize = 1000;
+const MAX_BATCH_SIZE: usize = 1000; // Operations per batch, the aggregator is locked while they are applied
+
+pub type EventStream = ScheduledStream<(usize, String), DomainError>;
+
+///
+/// Query parameters of the event endpoints. Browser ``EventSource`` clients cannot set
+/// custom headers, so they pass the start revision as ``?revision=N`` instead of ``X-Revision``.
+/// Subscribers may restrict the person events to ``?ids=1,7`` and the location events to ``?cities=Rome,Paris``.
+///
+#[derive(Deserialize, Debug)]
+pub struct EventQuery {
+    revision: Option<usize>,
+    ids: Option<String>,
+    cities: Option<String>
+}
+
+///
+/// Query parameters of the person endpoint. With any of ``limit``, ``after``, and ``snapshot``, the persons
+/// are returned in pages of at most ``limit`` persons with ids greater than ``after``. All pages of a ``snapshot``
+/// are consistent with the revision of its first page. Without ``snapshot``, a new snapshot is taken.
+/// Parameters ``city``, ``name`` (a prefix), and ``married`` filter the persons, see
+/// [PersonFilter](crate::domain::person_filter::PersonFilter).
+///
+#[derive(Deserialize, Debug)]
+pub struct PersonQuery {
+    limit: Option<usize>,
+    after: Option<PersonId>,
+    snapshot: Option<usize>,
+    #[serde(rename = "at-revision")]
+    at_revision: Option<usize>,
+    city: Option<String>,
+    name: Option<String>,
+    married: Option<bool>
+}
+
+///
+/// Query parameters of the location endpoint: ``at-revision`` asks for the locations as of an older revision.
+///
+#[derive(Deserialize, Debug)]
+pub struct LocationQuery {
+    #[serde(rename = "at-revision")]
+    at_revision: Option<usize>
+}
+
+///
+/// Query parameters of the combined event endpoint, analogous to [EventQuery](EventQuery).
+///
+#[derive(Deserialize, Debug)]
+pub struct CommitQuery {
+    commit: Option<usize>
+}
+
+// Message of the combined event stream
+#[derive(Serialize, Debug)]
+struct CommitEvent<'a> {
+    commit: usize,
+    revision: usize,
+    event: &'a RawValue
+}
+
+// Answer for applied batches, with one result per operation
+#[derive(Serialize, Debug)]
+struct BatchReply {
+    results: Vec<OperationReply>,
+    person_revisions: Option<RevisionRange>,
+    location_revisions: Option<RevisionRange>
+}
+
+#[derive(Serialize, Debug)]
+struct OperationReply {
+    id: PersonId,
+    revision: usize, // The revision of the person after the operation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    person: Option<PersonData> // Left out for deletions
 }
 
-pub async fn post_person(aggregator: MutexAggregator, path: &str, person: PersonData) -> Result<Box<dyn Reply>, Infallible> {
+#[derive(Serialize, Debug)]
+struct RevisionRange {
+    from: usize,
+    to: usize
+}
+
+///
+/// Query parameters of the paged event endpoints: the first revision and the maximum number of events.
+/// Like the event streams, the pages can be restricted to ``ids`` (person events) or ``cities`` (location events).
+///
+#[derive(Deserialize, Debug)]
+pub struct EventPageQuery {
+    from: Option<usize>,
+    limit: Option<usize>,
+    ids: Option<String>,
+    cities: Option<String>
+}
+
+// One page of events for pull-based consumers
+#[derive(Serialize, Debug)]
+struct EventPage<E> {
+    events: Vec<TimedEvent<E>>,
+    next_revision: usize // The revision to ask for in the next request
+}
+
+#[derive(Serialize, Debug)]
+struct TimedEvent<E> {
+    revision: usize,
+    time: u64, // Seconds since the epoch
+    event: E
+}
+
+pub async fn post_person(aggregator: MutexAggregator, path: &str, idempotency_key: Option<String>, person: PersonData) -> Result<Box<dyn Reply>, Infallible> {
     let mut aggregator = aggregator.lock().unwrap();
-    return match aggregator.insert(&person) {
+    let result = match idempotency_key {
+        Some(key) => aggregator.insert_idempotent(&person, &key),
+        None => aggregator.insert(&person).map(Idempotent::Done)
+    };
+    match result {
+        // A retry gets the original response, so it cannot tell whether the first request got through
+        Ok(Idempotent::Done(created) | Idempotent::Replayed(created)) => Ok(created_reply(path, created)),
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+fn created_reply(path: &str, (person_id, revision, person_data): (PersonId, usize, PersonData)) -> Box<dyn Reply> {
+    let location = format!("/{}/{}", path, person_id);
+    let response = reply::json(&person_data);
+    let response = reply::with_status(response, StatusCode::CREATED);
+    let response = reply::with_header(response,"Location", location);
+    let response = reply::with_header(response, "ETag", entity_tag(revision, Encoding::Json));
+    Box::new(response)
+}
+
+pub async fn post_batch(aggregator: MutexAggregator, operations: Vec<PersonOperation>) -> Result<Box<dyn Reply>, Infallible> {
+    if operations.len() > MAX_BATCH_SIZE {
+        let detail = format!("Batch holds {} operations, at most {} are allowed", operations.len(), MAX_BATCH_SIZE);
+        return Ok(Problem::payload_too_large(&detail).reply())
+    }
+    let mut aggregator = aggregator.lock().unwrap();
+    match aggregator.apply_batch(&operations) {
+        Ok(result) => Ok(batch_reply(result)),
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+fn batch_reply(result: BatchResult) -> Box<dyn Reply> {
+    let results = result.results.into_iter()
+        .map(|(id, revision, person)| OperationReply { id, revision, person })
+        .collect();
+    let range = |(from, to)| RevisionRange { from, to };
+    let person_revisions = result.person_revisions.map(range);
+    let location_revisions = result.location_revisions.map(range);
+    Box::new(reply::json(&BatchReply { results, person_revisions, location_revisions }))
+}
+
+pub async fn put_person(aggregator: MutexAggregator, path: &str, person_id: PersonId, if_match: Option<String>, person: PersonData) -> Result<Box<dyn Reply>, Infallible> {
+    let precondition = precondition(if_match.as_deref());
+    let mut aggregator = aggregator.lock().unwrap();
+    match aggregator.replace(person_id, &person, &precondition) {
+        Ok((true, revision, person)) => Ok(created_reply(path, (person_id, revision, person))),
+        Ok((false, revision, person)) => {
+            Ok(Box::new(reply::with_header(reply::json(&person), "ETag", entity_tag(revision, Encoding::Json))))
+        },
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+pub async fn patch_person(aggregator: MutexAggregator, person_id: PersonId, if_match: Option<String>, person: PersonPatch) -> Result<Box<dyn Reply>, Infallible> {
+    let precondition = precondition(if_match.as_deref());
+    let mut aggregator = aggregator.lock().unwrap();
+    match aggregator.update(person_id, &person, &precondition) {
+        Ok((revision, person)) => {
+            Ok(Box::new(reply::with_header(reply::json(&person), "ETag", entity_tag(revision, Encoding::Json))))
+        },
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+pub async fn delete_person(aggregator: MutexAggregator, person_id: PersonId, if_match: Option<String>) -> Result<Box<dyn Reply>, Infallible> {
+    let precondition = precondition(if_match.as_deref());
+    let mut aggregator = aggregator.lock().unwrap();
+    match aggregator.delete(person_id, &precondition) {
+        Ok(revision) => Ok(Box::new(reply::with_header(reply(), "ETag", entity_tag(revision, Encoding::Json)))),
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+pub async fn get_persons(aggregator: MutexAggregator, path: &str, query: PersonQuery, revision_header_name: &str, commit_header_name: &str, if_none_match: Option<String>, encoding: Encoding) -> Result<Box<dyn Reply>, Infallible> {
+    let paged = query.limit.is_some() || query.after.is_some() || query.snapshot.is_some();
+    if query.city.is_some() || query.name.is_some() || query.married.is_some() {
+        if paged || query.at_revision.is_some() {
+            return Ok(Problem::bad_request("Filters cannot be combined with paging or at-revision").reply())
+        }
+        let filter = PersonFilter { city: query.city, name: query.name, married: query.married };
+        return get_filtered_persons(aggregator, filter, revision_header_name, commit_header_name, if_none_match, encoding)
+    }
+    if let Some(revision) = query.at_revision {
+        if paged {
+            return Ok(Problem::bad_request("Parameter at-revision cannot be combined with paging").reply())
+        }
+        let result = aggregator.lock().unwrap().get_persons_at(revision);
+        return Ok(restored_reply(result, revision, revision_header_name, commit_header_name, if_none_match, encoding))
+    }
+    if paged {
+        return get_person_page(aggregator, path, query, revision_header_name, commit_header_name, if_none_match, encoding)
+    }
+    let mut aggregator = aggregator.lock().unwrap();
+    let result = aggregator.get_persons().and_then(|(revision, persons)| {
+        let commit_id = aggregator.get_commit_id()?;
+        Ok((revision, commit_id, persons))
+    });
+    return match result {
         Ok(result) => {
-            let (person_id, person_data) = result;
-            let location = format!("/{}/{}", path, person_id);
-            let response = reply::json(&person_data);
-            let response = reply::with_status(response, StatusCode::CREATED);
-            let response = reply::with_header(response,"Location", location);
-            Ok(Box::new(response))
+            let (revision, commit_id, persons) = result;
+            let headers = RevisionHeaders { revision_header_name, revision, commit_header_name, commit_id: Some(commit_id) };
+            Ok(aggregate_reply(&persons, headers, if_none_match, encoding))
         },
-        Err(error) => {
-            let message = ErrorResult{ error: error.to_string() };
-            Ok(Box::new(reply::with_status(reply::json(&message), StatusCode::INTERNAL_SERVER_ERROR)))
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+fn get_filtered_persons(aggregator: MutexAggregator, filter: PersonFilter, revision_header_name: &str, commit_header_name: &str, if_none_match: Option<String>, encoding: Encoding) -> Result<Box<dyn Reply>, Infallible> {
+    let mut aggregator = aggregator.lock().unwrap();
+    let result = aggregator.get_filtered_persons(&filter).and_then(|(revision, persons)| {
+        let commit_id = aggregator.get_commit_id()?;
+        Ok((revision, commit_id, persons))
+    });
+    match result {
+        Ok((revision, commit_id, persons)) => {
+            let headers = RevisionHeaders { revision_header_name, revision, commit_header_name, commit_id: Some(commit_id) };
+            Ok(aggregate_reply(&persons, headers, if_none_match, encoding))
+        },
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+///
+/// Replies with one page of persons. If there are more persons, header ``Link`` points to the next page
+/// of the same snapshot. Expired snapshots are answered with ``410 Gone``. A first page that was not
+/// modified is answered with ``304 Not Modified`` before a snapshot is taken.
+///
+fn get_person_page(aggregator: MutexAggregator, path: &str, query: PersonQuery, revision_header_name: &str, commit_header_name: &str, if_none_match: Option<String>, encoding: Encoding) -> Result<Box<dyn Reply>, Infallible> {
+    let after = query.after.unwrap_or(PersonId::from(0));
+    let limit = query.limit.unwrap_or(PERSON_PAGE_SIZE).clamp(1, MAX_PERSON_PAGE_SIZE);
+    let mut aggregator = aggregator.lock().unwrap();
+    if query.snapshot.is_none() && if_none_match.is_some() {
+        match aggregator.get_person_revision() {
+            Ok((revision, commit_id)) if is_not_modified(if_none_match.as_deref(), &entity_tag(revision, encoding)) => {
+                let headers = RevisionHeaders { revision_header_name, revision, commit_header_name, commit_id: Some(commit_id) };
+                return Ok(not_modified_reply(entity_tag(revision, encoding), headers))
+            },
+            Ok(_) => {},
+            Err(error) => return Ok(error_reply(&error))
         }
     }
+    match aggregator.get_person_page(query.snapshot, after, limit) {
+        Ok(Some(page)) => {
+            let headers = RevisionHeaders { revision_header_name, revision: page.revision, commit_header_name, commit_id: Some(page.commit_id) };
+            let response = aggregate_reply(&page.persons, headers, if_none_match, encoding);
+            match page.next {
+                Some(next) => Ok(Box::new(reply::with_header(response, "Link", next_link(path, page.snapshot, next, limit)))),
+                None => Ok(response)
+            }
+        },
+        Ok(None) => Ok(Problem::new(ProblemType::Gone, StatusCode::GONE, "Snapshot expired, reload the persons without snapshot").reply()),
+        Err(error) => Ok(error_reply(&error))
+    }
 }
 
-pub async fn patch_person(aggregator: MutexAggregator, person_id: PersonId, person: PersonPatch) -> Result<Box<dyn Reply>, Infallible> {
+pub async fn get_person(aggregator: MutexAggregator, person_id: PersonId, revision_header_name: &str, commit_header_name: &str, if_none_match: Option<String>, encoding: Encoding) -> Result<Box<dyn Reply>, Infallible> {
     let mut aggregator = aggregator.lock().unwrap();
-    return match aggregator.update(person_id, &person) {
+    let result = aggregator.get_person(person_id).and_then(|(revision, person)| {
+        let commit_id = aggregator.get_commit_id()?;
+        Ok((revision, commit_id, person))
+    });
+    match result {
         Ok(result) => {
-            match result {
-                Some(person) => Ok(Box::new(reply::json(&person))),
-                None => Ok(Box::new(reply::with_status("Person not found", StatusCode::NOT_FOUND)))
+            let (revision, commit_id, person) = result;
+            let headers = RevisionHeaders { revision_header_name, revision, commit_header_name, commit_id: Some(commit_id) };
+            match person {
+                // The entity tag holds the revision of the person, which is expected by header If-Match
+                Some((person_revision, person)) => Ok(tagged_reply(&person, person_revision, headers, if_none_match, encoding)),
+                None => Ok(headers.apply(Problem::not_found(&format!("Person {} not found", person_id)).reply()))
             }
         },
-        Err(error) => {
-            let message = ErrorResult{ error: error.to_string() };
-            Ok(Box::new(reply::with_status(reply::json(&message), StatusCode::INTERNAL_SERVER_ERROR)))
-        }
+        Err(error) => Ok(error_reply(&error))
     }
 }
 
-pub async fn delete_person(aggregator: MutexAggregator, person_id: PersonId) -> Result<Box<dyn Reply>, Infallible> {
+pub async fn get_location(aggregator: MutexAggregator, city: String, revision_header_name: &str, commit_header_name: &str, if_none_match: Option<String>, encoding: Encoding) -> Result<Box<dyn Reply>, Infallible> {
+    // Path parameters are percent-encoded, for example "New%20York"
+    let city = match percent_decode_str(&city).decode_utf8() {
+        Ok(city) => city.to_string(),
+        Err(error) => return Ok(Problem::bad_request(&error.to_string()).reply())
+    };
     let mut aggregator = aggregator.lock().unwrap();
-    return match aggregator.delete(person_id) {
+    let result = aggregator.get_location(&city).and_then(|(revision, location)| {
+        let commit_id = aggregator.get_commit_id()?;
+        Ok((revision, commit_id, location))
+    });
+    match result {
         Ok(result) => {
-            match result {
-                true => Ok(Box::new(reply())),
-                false => Ok(Box::new(reply::with_status("Person not found", StatusCode::NOT_FOUND)))
+            let (revision, commit_id, location) = result;
+            let headers = RevisionHeaders { revision_header_name, revision, commit_header_name, commit_id: Some(commit_id) };
+            match location {
+                Some(location) => Ok(aggregate_reply(&location, headers, if_none_match, encoding)),
+                None => Ok(headers.apply(Problem::not_found(&format!("Location {} not found", city)).reply()))
             }
         },
-        Err(error) => {
-            let message = ErrorResult{ error: error.to_string() };
-            Ok(Box::new(reply::with_status(reply::json(&message), StatusCode::INTERNAL_SERVER_ERROR)))
-        }
+        Err(error) => Ok(error_reply(&error))
     }
 }
 
-pub async fn get_persons(aggregator: MutexAggregator, revision_header_name: &str) -> Result<Box<dyn Reply>, Infallible> {
+pub async fn get_locations(aggregator: MutexAggregator, query: LocationQuery, revision_header_name: &str, commit_header_name: &str, if_none_match: Option<String>, encoding: Encoding) -> Result<Box<dyn Reply>, Infallible> {
+    if let Some(revision) = query.at_revision {
+        let result = aggregator.lock().unwrap().get_locations_at(revision);
+        return Ok(restored_reply(result, revision, revision_header_name, commit_header_name, if_none_match, encoding))
+    }
     let mut aggregator = aggregator.lock().unwrap();
-    return match aggregator.get_persons() {
+    let result = aggregator.get_locations().and_then(|(revision, locations)| {
+        let commit_id = aggregator.get_commit_id()?;
+        Ok((revision, commit_id, locations))
+    });
+    return match result {
         Ok(result) => {
-            let (revision, persons) = result;
-            Ok(Box::new(reply::with_header(reply::json(&persons), revision_header_name, revision)))
+            let (revision, commit_id, locations) = result;
+            let headers = RevisionHeaders { revision_header_name, revision, commit_header_name, commit_id: Some(commit_id) };
+            Ok(aggregate_reply(&locations, headers, if_none_match, encoding))
+        },
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+pub async fn get_events(aggregator: MutexAggregator, broadcaster: ArcBroadcaster, event_type: EventType, config: EventStreamConfig, query: EventQuery, from_revision: Option<usize>, last_event_id: Option<usize>) -> Result<Box<dyn Reply>, Infallible> {
+    let filter = match event_filter(event_type, query.ids.as_deref(), query.cities.as_deref()) {
+        Ok(filter) => filter,
+        Err(error) => return Ok(Problem::bad_request(&error).reply())
+    };
+    let from_revision = match start_revision(query.revision, from_revision, last_event_id) {
+        Ok(revision) => revision,
+        Err(error) => return Ok(Problem::bad_request(&error).reply())
+    };
+    match check_purged(&aggregator, event_type, from_revision) {
+        Ok(Some(first_revision)) => {
+            return Ok(Problem::gone(&purged_error(first_revision), first_revision).reply())
         },
-        Err(error) => {
-            let message = ErrorResult{ error: error.to_string() };
-            Ok(Box::new(reply::with_status(reply::json(&message), StatusCode::INTERNAL_SERVER_ERROR)))
+        Ok(None) => {},
+        Err(error) => return Ok(error_reply(&error))
+    }
+    let stream = event_stream(aggregator, broadcaster, event_type, config, from_revision);
+    let stream = stream.filter_map(move |(revision, event)| {
+        let event = match &filter {
+            Some(filter) => filter.apply(event),
+            None => Some(event)
+        };
+        future::ready(event.map(|event| Ok::<Event, Infallible>(Event::default().id(revision.to_string()).data(event))))
+    });
+    // The first message only tells the client how long to wait before reconnecting
+    let retry = stream::once(future::ready(Ok(Event::default().retry(config.retry))));
+    let stream = sse::keep_alive().interval(config.keep_alive).stream(retry.chain(stream));
+    Ok(Box::new(sse::reply(stream)))
+}
+
+///
+/// Replies with an event stream that starts with all aggregates of ``event_type`` in a ``snapshot`` message,
+/// followed by their change events. The aggregates and their revision are read in one transaction,
+/// so no change gets lost. Reconnecting clients resume after ``Last-Event-ID``,
+/// or receive the aggregates again if the subsequent events were already deleted.
+/// Other query ``parameters`` like filters or paging are rejected, because the stream would ignore them.
+///
+pub async fn follow_aggregates(aggregator: MutexAggregator, broadcaster: ArcBroadcaster, event_type: EventType, config: EventStreamConfig, parameters: Vec<String>, last_event_id: Option<usize>) -> Result<Box<dyn Reply>, Infallible> {
+    if !parameters.is_empty() {
+        return Ok(Problem::bad_request(&format!("Parameter follow cannot be combined with {}", parameters.join(", "))).reply())
+    }
+    let next_revision = match last_event_id {
+        Some(revision) => match revision.checked_add(1) {
+            Some(next_revision) => Some(next_revision),
+            None => return Ok(Problem::bad_request(&format!("Last-Event-ID {} is out of range", revision)).reply())
+        },
+        None => None
+    };
+    let resume_revision = match next_revision {
+        Some(revision) => match check_purged(&aggregator, event_type, revision) {
+            Ok(None) => Some(revision),
+            Ok(Some(_)) => None,
+            Err(error) => return Ok(error_reply(&error))
+        },
+        None => None
+    };
+    let (snapshot, from_revision) = match resume_revision {
+        Some(revision) => (None, revision),
+        None => match aggregates_json(&aggregator, event_type) {
+            Ok((revision, aggregates)) => {
+                let snapshot = Event::default().event("snapshot").id(revision.to_string()).data(aggregates);
+                (Some(snapshot), revision + 1)
+            },
+            Err(error) => return Ok(error_reply(&error))
         }
+    };
+    let stream = event_stream(aggregator, broadcaster, event_type, config, from_revision)
+        .map(|(revision, event)| Ok::<Event, Infallible>(Event::default().id(revision.to_string()).data(event)));
+    let retry = Event::default().retry(config.retry);
+    let head = stream::iter(std::iter::once(retry).chain(snapshot).map(Ok));
+    let stream = sse::keep_alive().interval(config.keep_alive).stream(head.chain(stream));
+    Ok(Box::new(sse::reply(stream)))
+}
+
+// Serialized aggregates of event_type together with their revision
+fn aggregates_json(aggregator: &MutexAggregator, event_type: EventType) -> DomainResult<(usize, String)> {
+    let mut aggregator = aggregator.lock().unwrap();
+    // Aggregates are plain data, errors should not happen, panic accepted
+    match event_type {
+        EventType::PERSON => aggregator.get_persons().map(|(revision, persons)| (revision, serde_json::to_string(&persons).unwrap())),
+        EventType::LOCATION => aggregator.get_locations().map(|(revision, locations)| (revision, serde_json::to_string(&locations).unwrap()))
     }
 }
 
-pub async fn get_locations(aggregator: MutexAggregator, revision_header_name: &str) -> Result<Box<dyn Reply>, Infallible> {
+pub async fn get_event_page(aggregator: MutexAggregator, event_type: EventType, query: EventPageQuery, encoding: Encoding) -> Result<Box<dyn Reply>, Infallible> {
+    let from_revision = query.from.unwrap_or(1).max(1);
+    let limit = query.limit.unwrap_or(EVENT_PAGE_SIZE).clamp(1, MAX_EVENT_PAGE_SIZE);
+    let filter = match event_filter(event_type, query.ids.as_deref(), query.cities.as_deref()) {
+        Ok(filter) => filter,
+        Err(error) => return Ok(Problem::bad_request(&error).reply())
+    };
     let mut aggregator = aggregator.lock().unwrap();
-    return match aggregator.get_locations() {
-        Ok(result) => {
-            let (revision, locations) = result;
-            Ok(Box::new(reply::with_header(reply::json(&locations), revision_header_name, revision)))
+    match aggregator.get_first_revision(event_type) {
+        Ok(first_revision) if is_purged(from_revision, first_revision) => {
+            return Ok(Problem::gone(&purged_error(first_revision), first_revision).reply())
+        },
+        Ok(_) => {},
+        Err(error) => return Ok(error_reply(&error))
+    }
+    match aggregator.get_timed_events(event_type, from_revision, limit) {
+        Ok(events) => {
+            // Filtered pages may be empty although there are later events, next_revision still advances
+            let next_revision = events.last().map_or(from_revision, |(revision, _, _)| revision + 1);
+            let events = match &filter {
+                Some(filter) => events.into_iter().filter_map(|(revision, time, event)| filter.apply(event).map(|event| (revision, time, event))).collect(),
+                None => events
+            };
+            // Events are stored as serialized JSON, so they can be embedded without parsing into values.
+            // Other encodings need the domain events, whose serde derives keep null apart from absent fields.
+            let response = match (encoding, event_type) {
+                (Encoding::Json, _) => encoding.reply(&event_page(events, next_revision, |event| RawValue::from_string(event).unwrap())),
+                (_, EventType::PERSON) => encoding.reply(&event_page(events, next_revision, |event| serde_json::from_str::<PersonEvent>(&event).unwrap())),
+                (_, EventType::LOCATION) => encoding.reply(&event_page(events, next_revision, |event| serde_json::from_str::<LocationEvent>(&event).unwrap()))
+            };
+            Ok(Box::new(response))
+        },
+        Err(error) => Ok(error_reply(&error))
+    }
+}
+
+fn event_page<E>(events: Vec<(usize, u64, String)>, next_revision: usize, parse: impl Fn(String) -> E) -> EventPage<E> {
+    let events = events.into_iter().map(|(revision, time, event)| TimedEvent { revision, time, event: parse(event) }).collect();
+    EventPage { events, next_revision }
+}
+
+pub async fn get_commit_events(aggregator: MutexAggregator, config: EventStreamConfig, query: CommitQuery, from_commit: Option<usize>, last_event_id: Option<usize>) -> Result<Box<dyn Reply>, Infallible> {
+    let from_commit = match start_revision(query.commit, from_commit, last_event_id) {
+        Ok(commit) => commit,
+        Err(error) => return Ok(Problem::bad_request(&error).reply())
+    };
+    let first_commit = aggregator.lock().unwrap().get_first_commit();
+    match first_commit {
+        Ok(first_commit) if is_purged(from_commit, first_commit) => {
+            return Ok(Problem::gone(&purged_error(first_commit), first_commit).reply())
         },
-        Err(error) => {
-            let message = ErrorResult{ error: error.to_string() };
-            Ok(Box::new(reply::with_status(reply::json(&message), StatusCode::INTERNAL_SERVER_ERROR)))
+        Ok(_) => {},
+        Err(error) => return Ok(error_reply(&error))
+    }
+    let commits = aggregator.lock().unwrap().subscribe_commits();
+    let trigger = Box::pin(WatchStream::from_changes(commits).map(|_| ()));
+    let fetcher = Box::new(CommitFetcher::new(aggregator, from_commit));
+    let stream = ScheduledStream::with_trigger(config.repeat_every, fetcher, trigger);
+    let stream = stream.flat_map(|commit| stream::iter(commit_messages(commit)));
+    let retry = stream::once(future::ready(Ok(Event::default().retry(config.retry))));
+    let stream = sse::keep_alive().interval(config.keep_alive).stream(retry.chain(stream));
+    Ok(Box::new(sse::reply(stream)))
+}
+
+///
+/// Converts the events of one commit into Server-Sent Events named by the event type.
+/// Only the last event carries the commit id, so that ``Last-Event-ID`` of a reconnecting
+/// client denotes the last commit that was delivered completely.
+///
+fn commit_messages((commit_id, events): CommitEvents) -> Vec<Result<Event, Infallible>> {
+    let last = events.len() - 1;
+    events.into_iter().enumerate().map(|(index, (event_type, revision, event))| {
+        let event: &RawValue = serde_json::from_str(&event).unwrap(); // Events are serialized JSON
+        let data = serde_json::to_string(&CommitEvent { commit: commit_id, revision, event }).unwrap();
+        let message = Event::default().event(event_type.name()).data(data);
+        Ok(if index == last { message.id(commit_id.to_string()) } else { message })
+    }).collect()
+}
+
+///
+/// Answers malformed query parameters with status ``400``. Routes that must not pass such requests
+/// to subsequent routes recover with this function.
+///
+pub async fn reject_invalid_query(rejection: Rejection) -> Result<Box<dyn Reply>, Rejection> {
+    match rejection.find::<InvalidQuery>() {
+        Some(error) => Ok(Problem::bad_request(&error.to_string()).reply()),
+        None => Err(rejection)
+    }
+}
+
+///
+/// Answers malformed query parameters, headers, and request bodies with status ``400``, like
+/// [reject_invalid_query](reject_invalid_query). Headers are malformed if they cannot be parsed,
+/// for example a revision that is not a number. Bodies above the size limit of a route are answered
+/// with ``413``, ``Accept`` headers without a supported media type with ``406``.
+/// All other rejections are left to warp, for example unknown paths.
+///
+pub async fn reject_malformed_request(rejection: Rejection) -> Result<Box<dyn Reply>, Rejection> {
+    if let Some(error) = rejection.find::<InvalidHeader>() {
+        return Ok(Problem::bad_request(&error.to_string()).reply())
+    }
+    if let Some(error) = rejection.find::<PayloadTooLarge>() {
+        return Ok(Problem::payload_too_large(&error.to_string()).reply())
+    }
+    if let Some(error) = rejection.find::<NotAcceptable>() {
+        return Ok(Problem::not_acceptable(&error.to_string()).reply())
+    }
+    match rejection.find::<BodyDeserializeError>() {
+        Some(error) => Ok(Problem::bad_request(&error.to_string()).reply()),
+        None => reject_invalid_query(rejection).await
+    }
+}
+
+// Revision and commit id of an aggregate response, together with the names of their headers.
+// Aggregates restored as of an older revision have no commit id.
+struct RevisionHeaders<'a> {
+    revision_header_name: &'a str,
+    revision: usize,
+    commit_header_name: &'a str,
+    commit_id: Option<usize>
+}
+
+impl RevisionHeaders<'_> {
+    fn apply(&self, response: impl Reply + 'static) -> Box<dyn Reply> {
+        let response = reply::with_header(response, self.revision_header_name, self.revision);
+        match self.commit_id {
+            Some(commit_id) => Box::new(reply::with_header(response, self.commit_header_name, commit_id)),
+            None => Box::new(response)
         }
     }
 }
 
-pub async fn get_events(aggregator: MutexAggregator, event_type: EventType, repeat_every_secs: u64, from_revision: Option<usize>) -> Result<impl Reply, Infallible> {
-    let from_revision = from_revision.unwrap_or(1);
-    let fetcher = Box::new(EventFetcher::new(aggregator, event_type, from_revision));
-    let stream = ScheduledStream::new(Duration::from_secs(repeat_every_secs), fetcher);
-    let stream = stream.map(move |item| {
-        Ok::<Event, Infallible>(Event::default().data(item))
-    });
-    Ok(sse::reply(stream))
+///
+/// Replies with the aggregate and a strong ``ETag`` derived from its revision.
+/// If header ``If-None-Match`` already names this ``ETag``, the reply is ``304 Not Modified`` without body.
+/// Each encoding is a separate representation, so it has its own ``ETag`` and the reply varies by ``Accept``.
+///
+fn aggregate_reply<T: Serialize>(aggregate: &T, headers: RevisionHeaders, if_none_match: Option<String>, encoding: Encoding) -> Box<dyn Reply> {
+    let revision = headers.revision;
+    tagged_reply(aggregate, revision, headers, if_none_match, encoding)
+}
+
+// Like aggregate_reply, but derives the ETag from a revision that is not the one of the revision header
+fn tagged_reply<T: Serialize>(aggregate: &T, revision: usize, headers: RevisionHeaders, if_none_match: Option<String>, encoding: Encoding) -> Box<dyn Reply> {
+    let entity_tag = entity_tag(revision, encoding);
+    if is_not_modified(if_none_match.as_deref(), &entity_tag) {
+        return not_modified_reply(entity_tag, headers)
+    }
+    headers.apply(reply::with_header(reply::with_header(encoding.reply(aggregate), "ETag", entity_tag), "Vary", "Accept"))
+}
+
+fn not_modified_reply(entity_tag: String, headers: RevisionHeaders) -> Box<dyn Reply> {
+    let response = reply::with_status(reply(), StatusCode::NOT_MODIFIED);
+    headers.apply(reply::with_header(reply::with_header(response, "ETag", entity_tag), "Vary", "Accept"))
+}
+
+// Web linking (RFC 8288) to the next page, which also carries the snapshot token
+fn next_link(path: &str, snapshot: usize, after: PersonId, limit: usize) -> String {
+    format!("</{}?snapshot={}&after={}&limit={}>; rel=\"next\"", path, snapshot, after, limit)
+}
+
+///
+/// Replies with aggregates restored as of ``revision``. Revisions that do not exist yet are answered with
+/// ``404 Not Found``, revisions whose events were already deleted or compacted with ``410 Gone``.
+///
+fn restored_reply<T: Serialize>(result: DomainResult<PointInTime<T>>, revision: usize, revision_header_name: &str, commit_header_name: &str, if_none_match: Option<String>, encoding: Encoding) -> Box<dyn Reply> {
+    match result {
+        Ok(PointInTime::Found(aggregates)) => {
+            let headers = RevisionHeaders { revision_header_name, revision, commit_header_name, commit_id: None };
+            aggregate_reply(&aggregates, headers, if_none_match, encoding)
+        },
+        Ok(PointInTime::Future(current)) => {
+            Problem::not_found(&format!("Revision {} does not exist yet, the current revision is {}", revision, current)).reply()
+        },
+        Ok(PointInTime::Purged(first)) => Problem::gone(&format!("Revisions before {} cannot be restored anymore", first), first).reply(),
+        Err(error) => error_reply(&error)
+    }
+}
+
+fn entity_tag(revision: usize, encoding: Encoding) -> String {
+    format!("\"{}{}\"", revision, encoding.tag_suffix())
+}
+
+///
+/// Returns the precondition of header ``If-Match``, which holds the person revisions named by the entity tags.
+/// The entity tags of all encodings name the same revision. Weak and unknown entity tags never match.
+///
+fn precondition(if_match: Option<&str>) -> Precondition {
+    match if_match.map(str::trim) {
+        None => Precondition::Unconditional,
+        Some("*") => Precondition::Exists,
+        Some(if_match) => Precondition::Revisions(if_match.split(',').filter_map(|tag| tagged_revision(tag.trim())).collect())
+    }
+}
+
+fn tagged_revision(entity_tag: &str) -> Option<usize> {
+    let tag = entity_tag.strip_prefix('"')?.strip_suffix('"')?;
+    let revision = [Encoding::Cbor, Encoding::MessagePack].iter()
+        .find_map(|encoding| tag.strip_suffix(encoding.tag_suffix()))
+        .unwrap_or(tag);
+    revision.parse().ok()
+}
+
+// Replies with the problem of error. A failed precondition also tells the current revision of the person
+// by header ETag, so that the client can reload the person.
+fn error_reply(error: &DomainError) -> Box<dyn Reply> {
+    let response = Problem::of(error).reply();
+    match error {
+        DomainError::PreconditionFailed(_, Some(revision)) => Box::new(reply::with_header(response, "ETag", entity_tag(*revision, Encoding::Json))),
+        _ => response
+    }
+}
+
+///
+/// Checks if ``If-None-Match`` matches ``entity_tag``. The header may hold a list of entity tags or ``*``.
+/// As required for ``If-None-Match``, weak tags ``W/"..."`` match as well.
+///
+fn is_not_modified(if_none_match: Option<&str>, entity_tag: &str) -> bool {
+    match if_none_match {
+        Some(tags) => tags.split(',').map(str::trim).any(|tag| {
+            tag == "*" || tag.trim_start_matches("W/") == entity_tag
+        }),
+        None => false
+    }
+}
+
+///
+/// Creates a stream of revisions and serialized events of ``event_type``, starting at ``from_revision``.
+/// The stream is shared by the SSE endpoints and the WebSocket endpoint.
+///
+pub fn event_stream(aggregator: MutexAggregator, broadcaster: ArcBroadcaster, event_type: EventType, config: EventStreamConfig, from_revision: usize) -> EventStream {
+    let trigger = Box::pin(WatchStream::from_changes(broadcaster.subscribe()).map(|_| ()));
+    let fetcher = Box::new(EventFetcher::new(aggregator, broadcaster, event_type, from_revision));
+    ScheduledStream::with_trigger(config.repeat_every, fetcher, trigger)
+}
+
+///
+/// Returns the oldest revision still available if events between ``from_revision``
+/// and that revision were already deleted, otherwise ``None``.
+///
+pub fn check_purged(aggregator: &MutexAggregator, event_type: EventType, from_revision: usize) -> DomainResult<Option<usize>> {
+    let first_revision = aggregator.lock().unwrap().get_first_revision(event_type)?;
+    Ok(if is_purged(from_revision, first_revision) { Some(first_revision) } else { None })
+}
+
+pub fn purged_error(first_revision: usize) -> String {
+    format!("Events before revision {} were deleted, reload the aggregates", first_revision)
+}
+
+///
+/// Creates the key filter requested by query parameter ``ids`` (person events) or ``cities`` (location events).
+///
+pub fn event_filter(event_type: EventType, ids: Option<&str>, cities: Option<&str>) -> Result<Option<EventFilter>, String> {
+    match (event_type, ids, cities) {
+        (_, None, None) => Ok(None),
+        (EventType::PERSON, Some(ids), None) => match EventFilter::for_persons(ids) {
+            Ok(filter) => Ok(Some(filter)),
+            Err(error) => Err(format!("Invalid person ids '{}': {}", ids, error))
+        },
+        (EventType::LOCATION, None, Some(cities)) => Ok(Some(EventFilter::for_locations(cities))),
+        (EventType::PERSON, _, Some(_)) => Err(String::from("Person events can only be filtered by ids")),
+        (EventType::LOCATION, Some(_), _) => Err(String::from("Location events can only be filtered by cities"))
+    }
+}
+
+///
+/// Determines the first revision to be delivered by an event stream. The precedence is
+/// 1. header ``Last-Event-ID``, which is sent by SSE clients on reconnect and denotes the
+///    last revision they received, so the stream continues with the subsequent revision,
+/// 2. query parameter ``revision``,
+/// 3. header ``X-Revision``.
+///
+/// Without any of them, the stream starts at revision 1. A ``Last-Event-ID`` without
+/// a subsequent revision is an error.
+///
+fn start_revision(query_revision: Option<usize>, header_revision: Option<usize>, last_event_id: Option<usize>) -> Result<usize, String> {
+    match last_event_id {
+        Some(revision) => revision.checked_add(1).ok_or_else(|| format!("Last-Event-ID {} is out of range", revision)),
+        None => Ok(query_revision.or(header_revision).unwrap_or(1))
+    }
+}
+
+///
+/// Checks if events between ``from_revision`` and ``first_revision`` (the oldest revision
+/// still available) were deleted. Revisions start at 1, so 0 requests nothing in the past.
+///
+fn is_purged(from_revision: usize, first_revision: usize) -> bool {
+    from_revision.max(1) < first_revision
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain::event_type::EventType;
+    use crate::domain::person_id::PersonId;
+    use crate::rest::event_filter::EventFilter;
+    use crate::rest::encoding::Encoding;
+    use warp::{Filter, Reply};
+    use warp::http::StatusCode;
+    use crate::domain::person_data::PersonData;
+    use crate::domain::precondition::Precondition;
+    use crate::domain::person_patch::PersonPatch;
+    use crate::domain::person_operation::PersonOperation;
+    use crate::util::patch::Patch;
+    use crate::rest::problem::PROBLEM_MEDIA_TYPE;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use crate::aggregator::aggregator_facade::AggregatorFacade;
+    use crate::rest::event_broadcaster::spawn_event_broadcaster;
+    use crate::rest::event_stream_config::EventStreamConfig;
+    use crate::rest::rest_handlers::{commit_messages, delete_person, entity_tag, event_filter, follow_aggregates, get_event_page, get_events, get_persons, is_not_modified, is_purged, next_link, patch_person, post_batch, precondition, put_person, reject_malformed_request, start_revision, EventPageQuery, EventQuery, PersonQuery, MAX_BATCH_SIZE};
+
+    #[test]
+    fn test_start_revision_default() {
+        assert_eq!(start_revision(None, None, None), Ok(1));
+    }
+
+    #[test]
+    fn test_start_revision_header() {
+        assert_eq!(start_revision(None, Some(5), None), Ok(5));
+    }
+
+    #[test]
+    fn test_start_revision_query() {
+        assert_eq!(start_revision(Some(3), None, None), Ok(3));
+        assert_eq!(start_revision(Some(3), Some(5), None), Ok(3));
+    }
+
+    #[test]
+    fn test_start_revision_last_event_id() {
+        assert_eq!(start_revision(None, None, Some(5)), Ok(6));
+        assert_eq!(start_revision(Some(3), Some(2), Some(5)), Ok(6));
+        assert!(start_revision(None, None, Some(usize::MAX)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_events_last_event_id_overflow() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let (broadcaster, handle) = spawn_event_broadcaster(&aggregator, EventType::PERSON, 16);
+        let config = EventStreamConfig::default();
+        let query = EventQuery { revision: None, ids: None, cities: None };
+        let reply = get_events(aggregator, broadcaster, EventType::PERSON, config, query, None, Some(usize::MAX)).await.unwrap();
+        let response = reply.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+        handle.abort();
+    }
+
+    #[test]
+    fn test_is_purged() {
+        assert!(!is_purged(0, 1));
+        assert!(!is_purged(1, 1));
+        assert!(!is_purged(3, 3));
+        assert!(!is_purged(4, 3));
+        assert!(is_purged(0, 2));
+        assert!(is_purged(2, 3));
+    }
+
+    #[test]
+    fn test_next_link() {
+        assert_eq!(next_link("persons", 3, PersonId::from(42), 100), r#"</persons?snapshot=3&after=42&limit=100>; rel="next""#);
+    }
+
+    #[test]
+    fn test_entity_tag() {
+        assert_eq!(entity_tag(7, Encoding::Json), r#""7""#);
+        assert_eq!(entity_tag(7, Encoding::Cbor), r#""7-cbor""#);
+    }
+
+    #[test]
+    fn test_precondition() {
+        assert_eq!(precondition(None), Precondition::Unconditional);
+        assert_eq!(precondition(Some("*")), Precondition::Exists);
+        assert_eq!(precondition(Some(r#""7""#)), Precondition::Revisions(vec![7]));
+        assert_eq!(precondition(Some(r#""7-cbor", "8-msgpack""#)), Precondition::Revisions(vec![7, 8]));
+        assert_eq!(precondition(Some(r#"W/"7", "7-json", 7"#)), Precondition::Revisions(vec![]));
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        assert!(!is_not_modified(None, r#""7""#));
+        assert!(is_not_modified(Some(r#""7""#), r#""7""#));
+        assert!(is_not_modified(Some(r#""5", W/"7""#), r#""7""#));
+        assert!(is_not_modified(Some("*"), r#""7""#));
+        assert!(!is_not_modified(Some(r#""6""#), r#""7""#));
+        assert!(!is_not_modified(Some("7"), r#""7""#));
+    }
+
+    #[test]
+    fn test_event_filter() {
+        assert_eq!(event_filter(EventType::PERSON, None, None), Ok(None));
+        assert_eq!(event_filter(EventType::PERSON, Some("1,7"), None), Ok(Some(EventFilter::for_persons("1,7").unwrap())));
+        assert_eq!(event_filter(EventType::LOCATION, None, Some("Rome")), Ok(Some(EventFilter::for_locations("Rome"))));
+        assert!(event_filter(EventType::PERSON, Some("x"), None).is_err());
+        assert!(event_filter(EventType::PERSON, None, Some("Rome")).is_err());
+        assert!(event_filter(EventType::LOCATION, Some("1"), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reject_malformed_request() {
+        let filter = warp::body::json::<PersonData>()
+            .map(|person: PersonData| Box::new(warp::reply::json(&person)) as Box<dyn Reply>)
+            .recover(reject_malformed_request);
+        let response = warp::test::request().method("POST").body(r#"{"name":"Ann"}"#).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = warp::test::request().method("POST").body(r#"{"city":"Rome"}"#).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+    }
+
+    #[tokio::test]
+    async fn test_follow_with_parameters() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let (broadcaster, handle) = spawn_event_broadcaster(&aggregator, EventType::PERSON, 16);
+        let config = EventStreamConfig { repeat_every: Duration::from_secs(1), keep_alive: Duration::from_secs(15), retry: Duration::from_secs(1) };
+        let reply = follow_aggregates(aggregator.clone(), broadcaster.clone(), EventType::PERSON, config, vec![String::from("city")], None).await.unwrap();
+        let response = reply.into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+        let reply = follow_aggregates(aggregator, broadcaster, EventType::PERSON, config, Vec::new(), Some(usize::MAX)).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::BAD_REQUEST);
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_get_events_retry_and_keep_alive() {
+        use warp::hyper::body::HttpBody;
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let (broadcaster, handle) = spawn_event_broadcaster(&aggregator, EventType::PERSON, 16);
+        let config = EventStreamConfig { repeat_every: Duration::from_secs(1), keep_alive: Duration::from_millis(50), retry: Duration::from_secs(2) };
+        let query = EventQuery { revision: None, ids: None, cities: None };
+        let reply = get_events(aggregator, broadcaster, EventType::PERSON, config, query, None, None).await.unwrap();
+        let mut body = reply.into_response().into_body();
+        let mut text = String::new();
+        // There are no events, so the retry field is followed by keep-alive comments only
+        while !text.contains("\n:") {
+            let chunk = tokio::time::timeout(Duration::from_secs(5), body.data()).await.unwrap().unwrap().unwrap();
+            text.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+        assert!(text.starts_with("retry:2000\n"));
+        assert!(!text.contains("data:"));
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_get_event_page_limit() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        aggregator.lock().unwrap().insert(&PersonData::new("Ann", None, None)).unwrap();
+        aggregator.lock().unwrap().insert(&PersonData::new("Bob", None, None)).unwrap();
+        // An empty page would never advance, so at least one event is returned
+        let query = EventPageQuery { from: None, limit: Some(0), ids: None, cities: None };
+        let response = get_event_page(aggregator, EventType::PERSON, query, Encoding::Json).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page["events"].as_array().unwrap().len(), 1);
+        assert_eq!(page["next_revision"], 2);
+    }
+
+    #[tokio::test]
+    async fn test_reject_malformed_header() {
+        // Like the event routes, which are tried after other routes
+        let filter = warp::path("persons").map(|| Box::new(warp::reply()) as Box<dyn Reply>)
+            .or(warp::path("events").and(warp::header::optional::<usize>("X-Commit"))
+                .map(|_: Option<usize>| Box::new(warp::reply()) as Box<dyn Reply>))
+            .unify()
+            .recover(reject_malformed_request);
+        let response = warp::test::request().path("/events").header("X-Commit", "5").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = warp::test::request().path("/events").header("X-Commit", "abc").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+        let response = warp::test::request().path("/unknown").reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_put_person_if_match_any() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let person = PersonData::new("Ann", None, None);
+        // If-Match: * requires that the person exists, so it must not be created
+        let reply = put_person(aggregator.clone(), "persons", PersonId::from(5), Some(String::from("*")), person.clone()).await.unwrap();
+        let response = reply.into_response();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+        assert_eq!(aggregator.lock().unwrap().get_persons().unwrap().0, 0);
+
+        let reply = put_person(aggregator.clone(), "persons", PersonId::from(5), None, person.clone()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::CREATED);
+        let reply = put_person(aggregator, "persons", PersonId::from(5), Some(String::from("*")), person).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_put_person_id_out_of_range() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let person = PersonData::new("Ann", None, None);
+        let reply = put_person(aggregator.clone(), "persons", PersonId::from(u64::MAX), None, person).await.unwrap();
+        let response = reply.into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+        let reply = delete_person(aggregator, PersonId::from(u64::MAX), None).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_patch_and_delete_if_match_any() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let if_match = || Some(String::from("*"));
+        let patch = PersonPatch::new(None, Patch::Value("Rome"), Patch::Absent);
+        let reply = patch_person(aggregator.clone(), PersonId::from(1), if_match(), patch.clone()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::PRECONDITION_FAILED);
+        let reply = delete_person(aggregator.clone(), PersonId::from(1), if_match()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::PRECONDITION_FAILED);
+        let reply = delete_person(aggregator.clone(), PersonId::from(1), None).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::NOT_FOUND);
+
+        aggregator.lock().unwrap().insert(&PersonData::new("Ann", None, None)).unwrap();
+        let reply = patch_person(aggregator.clone(), PersonId::from(1), if_match(), patch).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::OK);
+        let reply = delete_person(aggregator, PersonId::from(1), if_match()).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_get_person_page_not_modified() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        aggregator.lock().unwrap().insert(&PersonData::new("Ann", None, None)).unwrap();
+        let query = || PersonQuery { limit: Some(1), after: None, snapshot: None, at_revision: None, city: None, name: None, married: None };
+        let reply = get_persons(aggregator.clone(), "persons", query(), "X-Revision", "X-Commit", Some(String::from(r#""1""#)), Encoding::Json).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::NOT_MODIFIED);
+        // No snapshot was taken for the 304, so the next page starts the first snapshot
+        let page = aggregator.lock().unwrap().get_person_page(None, PersonId::from(0), 1).unwrap().unwrap();
+        assert_eq!(page.snapshot, 1);
+        aggregator.lock().unwrap().insert(&PersonData::new("Bob", None, None)).unwrap();
+        let reply = get_persons(aggregator, "persons", query(), "X-Revision", "X-Commit", Some(String::from(r#""1""#)), Encoding::Json).await.unwrap();
+        assert_eq!(reply.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_get_event_page_filtered() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        aggregator.lock().unwrap().insert(&PersonData::new("Ann", None, None)).unwrap();
+        aggregator.lock().unwrap().insert(&PersonData::new("Bob", None, None)).unwrap();
+        let query = EventPageQuery { from: None, limit: None, ids: Some(String::from("2")), cities: None };
+        let response = get_event_page(aggregator.clone(), EventType::PERSON, query, Encoding::Json).await.unwrap().into_response();
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let page: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(page["events"].as_array().unwrap().len(), 1);
+        assert_eq!(page["events"][0]["revision"], 2);
+        assert_eq!(page["next_revision"], 3);
+
+        let query = EventPageQuery { from: None, limit: None, ids: None, cities: Some(String::from("Rome")) };
+        let response = get_event_page(aggregator, EventType::PERSON, query, Encoding::Json).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_post_batch_too_large() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let operations = vec![PersonOperation::Insert { person: PersonData::new("Ann", None, None) }; MAX_BATCH_SIZE + 1];
+        let response = post_batch(aggregator.clone(), operations).await.unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+        assert_eq!(aggregator.lock().unwrap().get_persons().unwrap().0, 0);
+
+        // Like the batch route, which also limits the size of the body
+        let filter = warp::body::content_length_limit(16)
+            .and(warp::body::json::<Vec<PersonOperation>>())
+            .map(|_| Box::new(warp::reply()) as Box<dyn Reply>)
+            .recover(reject_malformed_request);
+        let response = warp::test::request().method("POST").body(r#"[{"op":"delete","id":1}]"#).reply(&filter).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response.headers()["Content-Type"], PROBLEM_MEDIA_TYPE);
+    }
+
+    #[test]
+    fn test_commit_messages() {
+        let events = vec![
+            (EventType::PERSON, 3, String::from(r#"{"1":{"city":"here"}}"#)),
+            (EventType::LOCATION, 2, String::from(r#"{"here":{"total":1}}"#))
+        ];
+        let messages: Vec<String> = commit_messages((5, events)).into_iter()
+            .map(|message| message.unwrap().to_string())
+            .collect();
+        assert_eq!(messages, vec![
+            "event:person\ndata:{\"commit\":5,\"revision\":3,\"event\":{\"1\":{\"city\":\"here\"}}}\n\n",
+            "event:location\ndata:{\"commit\":5,\"revision\":2,\"event\":{\"here\":{\"total\":1}}}\nid:5\n\n"
+        ]);
+    }
 }
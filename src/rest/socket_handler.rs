@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use futures_util::{future, stream, SinkExt, StreamExt};
+use futures_util::stream::BoxStream;
+use log::{debug, info, warn};
+use serde::{Serialize, Deserialize};
+use serde_json::value::RawValue;
+use tokio::time::{interval_at, Instant};
+use tokio_stream::StreamMap;
+use warp::ws::{Message, WebSocket};
+use crate::aggregator::aggregator_facade::MutexAggregator;
+use crate::domain::event_type::EventType;
+use crate::rest::event_broadcaster::ArcBroadcaster;
+use crate::rest::event_filter::EventFilter;
+use crate::rest::event_stream_config::EventStreamConfig;
+use crate::rest::rest_handlers::{check_purged, event_filter, event_stream, purged_error};
+
+///
+/// Control messages sent by WebSocket clients, for example
+/// ``{"action":"subscribe","stream":"person","revision":8}`` or ``{"action":"unsubscribe","stream":"person"}``.
+/// Like the query parameters of the event streams, ``ids`` and ``cities`` restrict a subscription to some aggregates.
+///
+#[derive(Deserialize, Debug, Eq, PartialEq)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ControlMessage {
+    Subscribe { stream: EventType, revision: Option<usize>, ids: Option<String>, cities: Option<String> },
+    Unsubscribe { stream: EventType }
+}
+
+// Change event of one stream, with the event as unmodified JSON merge patch
+#[derive(Serialize, Debug)]
+struct EventMessage<'a> {
+    stream: EventType,
+    revision: usize,
+    event: &'a RawValue
+}
+
+// Answer to a control message that could not be processed
+#[derive(Serialize, Debug)]
+struct ErrorMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream: Option<EventType>,
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    revision: Option<usize> // The oldest revision still available if events were deleted
+}
+
+fn error_message(stream: Option<EventType>, error: String, revision: Option<usize>) -> Message {
+    let message = ErrorMessage { stream, error, revision };
+    // Error messages are plain data, errors should not happen, panic accepted
+    Message::text(serde_json::to_string(&message).unwrap())
+}
+
+// The events of one subscription, followed by None when the event stream ends because reading events failed
+type Subscription = BoxStream<'static, Option<(usize, String)>>;
+
+// The event streams subscribed by one WebSocket client
+struct Subscriptions {
+    aggregator: MutexAggregator,
+    person_broadcaster: ArcBroadcaster,
+    location_broadcaster: ArcBroadcaster,
+    config: EventStreamConfig,
+    streams: StreamMap<EventType, Subscription>,
+    filters: HashMap<EventType, EventFilter>
+}
+
+impl Subscriptions {
+    // Processes a control message and returns an error message if it failed
+    fn control(&mut self, text: &str) -> Option<Message> {
+        match serde_json::from_str::<ControlMessage>(text) {
+            Ok(ControlMessage::Subscribe { stream, revision, ids, cities }) => {
+                match event_filter(stream, ids.as_deref(), cities.as_deref()) {
+                    Ok(filter) => self.subscribe(stream, revision.unwrap_or(1), filter),
+                    Err(error) => Some(error_message(Some(stream), error, None))
+                }
+            },
+            Ok(ControlMessage::Unsubscribe { stream }) => {
+                self.streams.remove(&stream);
+                self.filters.remove(&stream);
+                None
+            },
+            Err(error) => Some(error_message(None, error.to_string(), None))
+        }
+    }
+
+    // Starts (or restarts) the stream of ``event_type`` at ``from_revision``, restricted to the keys of ``filter``
+    fn subscribe(&mut self, event_type: EventType, from_revision: usize, filter: Option<EventFilter>) -> Option<Message> {
+        match check_purged(&self.aggregator, event_type, from_revision) {
+            Ok(Some(first_revision)) => Some(error_message(Some(event_type), purged_error(first_revision), Some(first_revision))),
+            Ok(None) => {
+                let broadcaster = match event_type {
+                    EventType::PERSON => self.person_broadcaster.clone(),
+                    EventType::LOCATION => self.location_broadcaster.clone()
+                };
+                let stream = event_stream(self.aggregator.clone(), broadcaster, event_type, self.config, from_revision)
+                    .map(Some)
+                    .chain(stream::once(future::ready(None)));
+                self.streams.insert(event_type, stream.boxed());
+                match filter {
+                    Some(filter) => self.filters.insert(event_type, filter),
+                    None => self.filters.remove(&event_type)
+                };
+                None
+            },
+            Err(error) => Some(error_message(Some(event_type), error.to_string(), None))
+        }
+    }
+
+    // Returns the message of an item of ``stream``, or ``None`` if the filter of the stream skips the event.
+    // A failed stream is dropped, and the client is told to subscribe again.
+    fn stream_message(&mut self, stream: EventType, item: Option<(usize, String)>) -> Option<Message> {
+        match item {
+            Some((revision, event)) => self.event_message(stream, revision, event),
+            None => {
+                self.streams.remove(&stream);
+                self.filters.remove(&stream);
+                Some(error_message(Some(stream), String::from("Reading events failed, subscribe again"), None))
+            }
+        }
+    }
+
+    // Returns the message of an event of ``stream``, or ``None`` if the filter of the stream skips it
+    fn event_message(&self, stream: EventType, revision: usize, event: String) -> Option<Message> {
+        let event = match self.filters.get(&stream) {
+            Some(filter) => filter.apply(event)?,
+            None => event
+        };
+        Some(event_message(stream, revision, &event))
+    }
+}
+
+fn event_message(stream: EventType, revision: usize, event: &str) -> Message {
+    // Events are stored as serialized JSON, so they can be embedded without parsing into values
+    let message = serde_json::from_str::<&RawValue>(event)
+        .and_then(|event| serde_json::to_string(&EventMessage { stream, revision, event }));
+    match message {
+        Ok(message) => Message::text(message),
+        Err(error) => {
+            warn!("Event {} of stream {:?} is not valid JSON: {}", revision, stream, error);
+            error_message(Some(stream), format!("Event {} could not be read", revision), None)
+        }
+    }
+}
+
+///
+/// Serves the person and location change streams over one WebSocket.
+/// Clients select the streams with [ControlMessage](ControlMessage)s.
+/// Every change event is sent as ``{"stream":"person","revision":8,"event":{"2":{"name":"Bob"}}}``.
+/// If reading the events of a stream fails, the client receives an error message for the stream
+/// and has to subscribe again. Idle sockets receive a ping every [keep_alive](EventStreamConfig::keep_alive) interval.
+///
+pub async fn handle_socket(socket: WebSocket, aggregator: MutexAggregator, person_broadcaster: ArcBroadcaster,
+                           location_broadcaster: ArcBroadcaster, config: EventStreamConfig) {
+    info!("Open event socket");
+    let (mut sink, mut source) = socket.split();
+    let mut subscriptions = Subscriptions {
+        aggregator, person_broadcaster, location_broadcaster, config, streams: StreamMap::new(), filters: HashMap::new()
+    };
+    let mut keep_alive = interval_at(Instant::now() + config.keep_alive, config.keep_alive);
+    loop {
+        let message = tokio::select! {
+            input = source.next() => match input {
+                Some(Ok(input)) if input.is_close() => break,
+                Some(Ok(input)) => match input.to_str() {
+                    Ok(text) => subscriptions.control(text),
+                    Err(_) => None // Pongs and binary messages are ignored
+                },
+                Some(Err(error)) => {
+                    debug!("Event socket failed: {:?}", error);
+                    break
+                },
+                None => break
+            },
+            Some((stream, item)) = subscriptions.streams.next() => subscriptions.stream_message(stream, item),
+            _ = keep_alive.tick() => Some(Message::ping(Vec::new()))
+        };
+        if let Some(message) = message {
+            if sink.send(message).await.is_err() {
+                break
+            }
+            keep_alive.reset();
+        }
+    }
+    info!("Close event socket");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio_stream::StreamMap;
+    use crate::aggregator::aggregator_facade::AggregatorFacade;
+    use crate::domain::event_type::EventType;
+    use crate::rest::event_broadcaster::spawn_event_broadcaster;
+    use crate::rest::event_stream_config::EventStreamConfig;
+    use crate::rest::socket_handler::{ControlMessage, error_message, event_message, Subscriptions};
+
+    #[test]
+    fn test_control_subscribe() {
+        let message = serde_json::from_str::<ControlMessage>(r#"{"action":"subscribe","stream":"person","revision":8}"#);
+        assert_eq!(message.unwrap(), ControlMessage::Subscribe { stream: EventType::PERSON, revision: Some(8), ids: None, cities: None });
+        let message = serde_json::from_str::<ControlMessage>(r#"{"action":"subscribe","stream":"location"}"#);
+        assert_eq!(message.unwrap(), ControlMessage::Subscribe { stream: EventType::LOCATION, revision: None, ids: None, cities: None });
+        let message = serde_json::from_str::<ControlMessage>(r#"{"action":"subscribe","stream":"location","cities":"Rome,Paris"}"#);
+        let cities = Some(String::from("Rome,Paris"));
+        assert_eq!(message.unwrap(), ControlMessage::Subscribe { stream: EventType::LOCATION, revision: None, ids: None, cities });
+    }
+
+    #[test]
+    fn test_control_unsubscribe() {
+        let message = serde_json::from_str::<ControlMessage>(r#"{"action":"unsubscribe","stream":"location"}"#);
+        assert_eq!(message.unwrap(), ControlMessage::Unsubscribe { stream: EventType::LOCATION });
+    }
+
+    #[test]
+    fn test_control_invalid() {
+        assert!(serde_json::from_str::<ControlMessage>(r#"{"action":"publish","stream":"person"}"#).is_err());
+        assert!(serde_json::from_str::<ControlMessage>(r#"{"action":"subscribe","stream":"city"}"#).is_err());
+        assert!(serde_json::from_str::<ControlMessage>(r#"{"action":"subscribe"}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_filtered() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let (person_broadcaster, person_reader) = spawn_event_broadcaster(&aggregator, EventType::PERSON, 16);
+        let (location_broadcaster, location_reader) = spawn_event_broadcaster(&aggregator, EventType::LOCATION, 16);
+        let config = EventStreamConfig { repeat_every: Duration::from_secs(1), keep_alive: Duration::from_secs(15), retry: Duration::from_secs(1) };
+        let mut subscriptions = Subscriptions {
+            aggregator, person_broadcaster, location_broadcaster, config, streams: StreamMap::new(), filters: HashMap::new()
+        };
+        assert!(subscriptions.control(r#"{"action":"subscribe","stream":"person","ids":"2"}"#).is_none());
+        assert!(subscriptions.event_message(EventType::PERSON, 1, String::from(r#"{"1":{"name":"Ann"}}"#)).is_none());
+        let message = subscriptions.event_message(EventType::PERSON, 2, String::from(r#"{"2":{"name":"Bob"}}"#));
+        assert_eq!(message.unwrap().to_str().unwrap(), r#"{"stream":"person","revision":2,"event":{"2":{"name":"Bob"}}}"#);
+
+        // Subscribing again without ids drops the filter
+        assert!(subscriptions.control(r#"{"action":"subscribe","stream":"person"}"#).is_none());
+        assert!(subscriptions.event_message(EventType::PERSON, 1, String::from(r#"{"1":{"name":"Ann"}}"#)).is_some());
+        let message = subscriptions.control(r#"{"action":"subscribe","stream":"location","ids":"1"}"#);
+        assert_eq!(message.unwrap().to_str().unwrap(), r#"{"stream":"location","error":"Location events can only be filtered by cities"}"#);
+        person_reader.abort();
+        location_reader.abort();
+    }
+
+    #[tokio::test]
+    async fn test_stream_message_end() {
+        let aggregator = Arc::new(Mutex::new(AggregatorFacade::new(":memory:").unwrap()));
+        let (person_broadcaster, person_reader) = spawn_event_broadcaster(&aggregator, EventType::PERSON, 16);
+        let (location_broadcaster, location_reader) = spawn_event_broadcaster(&aggregator, EventType::LOCATION, 16);
+        let config = EventStreamConfig { repeat_every: Duration::from_secs(1), keep_alive: Duration::from_secs(15), retry: Duration::from_secs(1) };
+        let mut subscriptions = Subscriptions {
+            aggregator, person_broadcaster, location_broadcaster, config, streams: StreamMap::new(), filters: HashMap::new()
+        };
+        assert!(subscriptions.control(r#"{"action":"subscribe","stream":"person","ids":"2"}"#).is_none());
+        let message = subscriptions.stream_message(EventType::PERSON, None);
+        assert_eq!(message.unwrap().to_str().unwrap(), r#"{"stream":"person","error":"Reading events failed, subscribe again"}"#);
+        assert!(subscriptions.streams.is_empty());
+        assert!(subscriptions.filters.is_empty());
+        person_reader.abort();
+        location_reader.abort();
+    }
+
+    #[test]
+    fn test_event_message() {
+        let message = event_message(EventType::PERSON, 8, r#"{"2":{"name":"Bob"}}"#);
+        assert_eq!(message.to_str().unwrap(), r#"{"stream":"person","revision":8,"event":{"2":{"name":"Bob"}}}"#);
+        let message = event_message(EventType::PERSON, 9, r#"{"2":"#);
+        assert_eq!(message.to_str().unwrap(), r#"{"stream":"person","error":"Event 9 could not be read"}"#);
+    }
+
+    #[test]
+    fn test_error_message() {
+        let message = error_message(Some(EventType::LOCATION), String::from("gone"), Some(5));
+        assert_eq!(message.to_str().unwrap(), r#"{"stream":"location","error":"gone","revision":5}"#);
+        let message = error_message(None, String::from("invalid"), None);
+        assert_eq!(message.to_str().unwrap(), r#"{"error":"invalid"}"#);
+    }
+}
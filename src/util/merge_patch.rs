@@ -0,0 +1,144 @@
+use serde_json::{Map, Value};
+
+///
+/// Composes two [JSON Merge Patches](https://www.rfc-editor.org/rfc/rfc7386) in place, so that
+/// applying ``first`` afterwards has the same effect as applying ``first`` and then ``second``.
+/// In contrast to applying a patch, ``null`` values are kept, because they must delete
+/// the corresponding values of the target.
+///
+/// Merge patches cannot express "replace the whole object". A patch that sets an object after
+/// a ``null`` is therefore merged into the target rather than replacing it, so fields of the target
+/// that the object leaves out survive. Callers must not compose such patches, see
+/// [compact_before](crate::database::event_table::EventTable::compact_before).
+///
+pub fn compose(first: &mut Value, second: Value) {
+    match (first, second) {
+        (Value::Object(first), Value::Object(second)) => {
+            for (key, value) in second {
+                match first.get_mut(&key) {
+                    Some(current) if current.is_object() && value.is_object() => compose(current, value),
+                    _ => { first.insert(key, value); }
+                }
+            }
+        },
+        (first, second) => *first = second
+    }
+}
+
+///
+/// Applies a [JSON Merge Patch](https://www.rfc-editor.org/rfc/rfc7386) to ``target``,
+/// where ``null`` values delete the corresponding values of the target.
+///
+pub fn apply(target: &mut Value, patch: Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !target.is_object() {
+                *target = Value::Object(Map::new());
+            }
+            let target = target.as_object_mut().unwrap();
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    apply(target.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        },
+        patch => *target = patch
+    }
+}
+
+///
+/// Returns the merge patch that reverts ``patch`` on ``before``, the state of the target prior to the patch.
+/// ``None`` stands for a target that did not exist, so the inverse patch is ``null``.
+///
+pub fn invert(before: Option<&Value>, patch: &Value) -> Value {
+    match (before, patch) {
+        (None, _) => Value::Null,
+        (Some(Value::Object(before)), Value::Object(patch)) => {
+            let inverse = patch.iter().map(|(key, value)| (key.clone(), invert(before.get(key), value))).collect();
+            Value::Object(inverse)
+        },
+        (Some(before), _) => before.clone()
+    }
+}
+
+///
+/// Like [invert], but for a change event with the key of the aggregate (person id or city) as top-level key.
+/// ``before`` is the serialized aggregate prior to the event, if it existed.
+///
+pub fn invert_event(key: &str, before: Option<Value>, event: &Value) -> Value {
+    let mut state = Map::new();
+    if let Some(before) = before {
+        state.insert(key.to_string(), before);
+    }
+    invert(Some(&Value::Object(state)), event)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use crate::util::merge_patch::{apply, compose, invert, invert_event};
+
+    #[test]
+    fn test_compose_fields() {
+        let mut first = json!({"1":{"name":"Ann","city":"Rome"}});
+        compose(&mut first, json!({"1":{"city":"Paris","spouse":2}}));
+        assert_eq!(first, json!({"1":{"name":"Ann","city":"Paris","spouse":2}}));
+    }
+
+    #[test]
+    fn test_compose_null_field() {
+        let mut first = json!({"1":{"name":"Ann","city":"Rome"}});
+        compose(&mut first, json!({"1":{"city":null}}));
+        assert_eq!(first, json!({"1":{"name":"Ann","city":null}}));
+    }
+
+    #[test]
+    fn test_compose_tombstone() {
+        let mut first = json!({"Rome":{"total":1,"married":0}});
+        compose(&mut first, json!({"Rome":null}));
+        assert_eq!(first, json!({"Rome":null}));
+        compose(&mut first, json!({"Rome":{"total":1,"married":1}}));
+        assert_eq!(first, json!({"Rome":{"total":1,"married":1}}));
+    }
+
+    #[test]
+    fn test_compose_scalar() {
+        let mut first = Value::Null;
+        compose(&mut first, json!({"total":1}));
+        assert_eq!(first, json!({"total":1}));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut target = json!({"1":{"name":"Ann","city":"Rome"},"2":{"name":"Bob"}});
+        apply(&mut target, json!({"1":{"city":null,"spouse":2},"2":null,"3":{"name":"Cam"}}));
+        assert_eq!(target, json!({"1":{"name":"Ann","spouse":2},"3":{"name":"Cam"}}));
+    }
+
+    #[test]
+    fn test_invert() {
+        let before = json!({"name":"Ann","city":"Rome"});
+        assert_eq!(invert(Some(&before), &json!({"city":"Paris","spouse":2})), json!({"city":"Rome","spouse":null}));
+        assert_eq!(invert(Some(&before), &Value::Null), before);
+        assert_eq!(invert(None, &json!({"name":"Ann"})), Value::Null);
+    }
+
+    #[test]
+    fn test_invert_event() {
+        assert_eq!(invert_event("1", None, &json!({"1":{"name":"Ann"}})), json!({"1":null}));
+        assert_eq!(invert_event("1", Some(json!({"name":"Ann"})), &json!({"1":null})), json!({"1":{"name":"Ann"}}));
+        assert_eq!(invert_event("1", Some(json!({"name":"Ann"})), &json!({"1":{"city":"Rome"}})), json!({"1":{"city":null}}));
+    }
+
+    #[test]
+    fn test_apply_inverse() {
+        let before = json!({"name":"Ann","city":"Rome"});
+        let patch = json!({"city":null,"spouse":2});
+        let mut target = before.clone();
+        apply(&mut target, patch.clone());
+        apply(&mut target, invert(Some(&before), &patch));
+        assert_eq!(target, before);
+    }
+}
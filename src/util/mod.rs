@@ -3,3 +3,5 @@ pub mod timestamp;
 pub mod scheduled_stream;
 pub mod deletion_scheduler;
 pub mod serde_and_verify;
+pub mod ring_buffer;
+pub mod merge_patch;
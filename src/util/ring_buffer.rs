@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+///
+/// A buffer of limited capacity for items with ascending revisions.
+/// If the buffer is full, pushing a new item drops the oldest one.
+///
+/// The buffer knows the revision that follows the last pushed item, so it can tell
+/// whether a reader is up-to-date, can be served from the buffer, or must fall back
+/// to another source because the requested items were already dropped.
+///
+pub struct RingBuffer<T> {
+    capacity: usize,
+    items: VecDeque<(usize, T)>,
+    next_revision: usize
+}
+
+impl<T: Clone> RingBuffer<T> {
+    pub fn new(capacity: usize, next_revision: usize) -> Self {
+        Self {
+            capacity,
+            items: VecDeque::with_capacity(capacity),
+            next_revision
+        }
+    }
+
+    pub fn next_revision(&self) -> usize {
+        self.next_revision
+    }
+
+    pub fn push(&mut self, revision: usize, item: T) {
+        if self.items.len() == self.capacity {
+            self.items.pop_front();
+        }
+        self.items.push_back((revision, item));
+        self.next_revision = revision + 1;
+    }
+
+    ///
+    /// Returns all items with a revision greater or equal ``from_revision``.
+    /// Returns ``None`` if items before ``from_revision`` may have been dropped,
+    /// i.e. the caller must read them from elsewhere.
+    ///
+    pub fn read_from(&self, from_revision: usize) -> Option<Vec<(usize, T)>> {
+        if from_revision >= self.next_revision {
+            return Some(Vec::new())
+        }
+        match self.items.front() {
+            Some((first, _)) if *first <= from_revision => {
+                Some(self.items.iter()
+                    .filter(|(revision, _)| *revision >= from_revision)
+                    .cloned()
+                    .collect())
+            },
+            _ => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::ring_buffer::RingBuffer;
+
+    #[test]
+    fn test_read_empty() {
+        let buffer : RingBuffer<&str> = RingBuffer::new(2, 5);
+        assert_eq!(buffer.read_from(5), Some(vec![]));
+        assert_eq!(buffer.read_from(4), None);
+    }
+
+    #[test]
+    fn test_read_from() {
+        let mut buffer = RingBuffer::new(2, 1);
+        buffer.push(1, "foo");
+        buffer.push(2, "bar");
+        assert_eq!(buffer.next_revision(), 3);
+        assert_eq!(buffer.read_from(1), Some(vec![(1, "foo"), (2, "bar")]));
+        assert_eq!(buffer.read_from(2), Some(vec![(2, "bar")]));
+        assert_eq!(buffer.read_from(3), Some(vec![]));
+    }
+
+    #[test]
+    fn test_read_dropped() {
+        let mut buffer = RingBuffer::new(2, 1);
+        buffer.push(1, "foo");
+        buffer.push(2, "bar");
+        buffer.push(3, "baz");
+        assert_eq!(buffer.read_from(1), None);
+        assert_eq!(buffer.read_from(2), Some(vec![(2, "bar"), (3, "baz")]));
+    }
+}
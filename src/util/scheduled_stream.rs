@@ -10,20 +10,35 @@ use tokio::time::{Interval, interval};
 
 ///
 /// Trait for custom fetcher implementations needed by [ScheduledStream](ScheduledStream).
+/// Fetchers that deliver items in pages return ``true`` from ``has_more()`` as long as
+/// further items are available, so that the next page is fetched without waiting.
 ///
 pub trait Fetcher<T, E> {
     fn fetch(&mut self) -> Result<Vec<T>, E>;
+
+    fn has_more(&self) -> bool {
+        false
+    }
 }
 
 pub type BoxedFetcher<T, E> = Box<dyn Fetcher<T, E> + Send>;
 
+/// A stream whose items signal that new data is available for fetching.
+pub type BoxedTrigger = Pin<Box<dyn Stream<Item = ()> + Send>>;
+
 ///
 /// An implementation of [Stream](futures_util::Stream) that periodically fetches items
 /// from a source through a [Fetcher](Fetcher). While ``Fetcher::fetch()`` returns a vector
 /// of items, method ``poll_next()`` returns the items one-by-one, utilizing a buffer.
 ///
+/// If the stream is constructed with a trigger, it also fetches whenever the trigger
+/// yields an item. The interval then serves as fallback only.
+///
+/// A new batch is fetched only after the buffer was drained.
+///
 pub struct ScheduledStream<T, E> {
     interval: Interval,
+    trigger: Option<BoxedTrigger>,
     buffer: Box<VecDeque<T>>,
     fetcher: BoxedFetcher<T, E>
 }
@@ -32,18 +47,46 @@ impl<T, E> ScheduledStream<T, E> {
     pub fn new(duration: Duration, fetcher: BoxedFetcher<T, E>) -> Self {
         Self {
             interval: interval(duration),
+            trigger: None,
             buffer: Box::new(VecDeque::new()),
             fetcher
         }
     }
+
+    pub fn with_trigger(duration: Duration, fetcher: BoxedFetcher<T, E>, trigger: BoxedTrigger) -> Self {
+        Self {
+            trigger: Some(trigger),
+            ..Self::new(duration, fetcher)
+        }
+    }
+
+    // Returns true if the trigger fired. A terminated trigger is dropped, so that only the interval remains.
+    fn poll_trigger(&mut self, cx: &mut Context<'_>) -> bool {
+        if let Some(trigger) = self.trigger.as_mut() {
+            match trigger.as_mut().poll_next(cx) {
+                Poll::Ready(Some(())) => return true,
+                Poll::Ready(None) => self.trigger = None,
+                Poll::Pending => {}
+            }
+        }
+        false
+    }
 }
 
 impl<T, E: Debug> Stream for ScheduledStream<T, E> {
     type Item = T;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
-        if self.buffer.len() == 0 {
-            ready!(self.interval.poll_tick(cx));
+        // Loop until the buffer is filled or the interval is pending. Returning Poll::Pending
+        // after an empty fetch would leave the stream without a registered waker.
+        while self.buffer.is_empty() {
+            if !self.fetcher.has_more() {
+                if self.poll_trigger(cx) {
+                    self.interval.reset(); // Postpone the fallback
+                } else {
+                    ready!(self.interval.poll_tick(cx));
+                }
+            }
             match self.fetcher.fetch() {
                 Ok(batch) => {
                     for item in batch {
@@ -56,17 +99,15 @@ impl<T, E: Debug> Stream for ScheduledStream<T, E> {
                 }
             }
         }
-        return match self.buffer.pop_front() {
-            Some(x) => Poll::Ready(Some(x)),
-            None => Poll::Pending
-        }
+        Poll::Ready(self.buffer.pop_front())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
-    use futures_util::StreamExt;
+    use futures_util::{stream, StreamExt};
+    use tokio::time::timeout;
     use crate::util::scheduled_stream::{Fetcher, ScheduledStream};
 
     #[derive(thiserror::Error,Debug)]
@@ -77,12 +118,17 @@ mod tests {
 
     struct TestFetcher {
         batches: Vec<Vec<&'static str>>,
-        index: usize
+        index: usize,
+        paged: bool
     }
 
     impl TestFetcher {
         fn new(batches: Vec<Vec<&'static str>>) -> Self {
-            Self { batches, index: 0 }
+            Self { batches, index: 0, paged: false }
+        }
+
+        fn paged(batches: Vec<Vec<&'static str>>) -> Self {
+            Self { batches, index: 0, paged: true }
         }
     }
 
@@ -95,6 +141,10 @@ mod tests {
             self.index += 1;
             Ok(iter.map(|y| String::from(*y)).collect())
         }
+
+        fn has_more(&self) -> bool {
+            self.paged
+        }
     }
 
     #[tokio::test]
@@ -107,6 +157,28 @@ mod tests {
         exec_test(vec![vec!["1"], vec!["2","3"], vec![]], vec!["1","2","3"]).await
     }
 
+    #[tokio::test]
+    async fn test_trigger() {
+        // Every trigger item postpones the interval, so only the trigger causes fetches.
+        // The fourth fetch returns an error, which ends the stream.
+        let g = Box::new(TestFetcher::new(vec![vec!["1"], vec![], vec!["2","3"]]));
+        let t = Box::pin(stream::iter(vec![(), (), (), ()]));
+        let s = ScheduledStream::with_trigger(Duration::from_secs(3600), g, t);
+        let v = timeout(Duration::from_secs(1), s.collect::<Vec<String>>()).await;
+        assert!(v.is_ok());
+        assert_eq!(v.unwrap(), vec!["1","2","3"]);
+    }
+
+    #[tokio::test]
+    async fn test_has_more() {
+        // All fetches are caused by has_more(), the interval never fires
+        let g = Box::new(TestFetcher::paged(vec![vec!["1","2"], vec!["3"]]));
+        let s = ScheduledStream::new(Duration::from_secs(3600), g);
+        let v = timeout(Duration::from_secs(1), s.collect::<Vec<String>>()).await;
+        assert!(v.is_ok());
+        assert_eq!(v.unwrap(), vec!["1","2","3"]);
+    }
+
     async fn exec_test(data: Vec<Vec<&'static str>>, ref_results: Vec<&str>) {
         let g = Box::new(TestFetcher::new(data));
         let mut s = ScheduledStream::new(Duration::from_millis(3), g);